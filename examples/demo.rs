@@ -0,0 +1,43 @@
+use paxos::fault::{FaultConfig, LinkFaults};
+use paxos::{Client, Cluster};
+use std::env;
+use std::thread;
+use std::time::Duration;
+
+const NUM_PROPOSERS: usize = 1;
+const NUM_ACCEPTORS: usize = 3;
+const NUM_LEARNERS: usize = 1;
+
+fn main() {
+    let client = Client::new(0);
+    let mut builder = Cluster::builder()
+        .proposers(NUM_PROPOSERS)
+        .acceptors(NUM_ACCEPTORS)
+        .learners(NUM_LEARNERS);
+    if env::args().any(|arg| arg == "--faults") {
+        builder = builder.faults(FaultConfig::new(LinkFaults {
+            drop: 0.1,
+            duplicate: 0.05,
+            reorder: 0.1,
+            max_delay: Duration::from_millis(20),
+            ..LinkFaults::default()
+        }));
+    }
+    let cluster = builder.build();
+    let storage = cluster.storage();
+    let proposer = cluster.proposer(0);
+
+    client.consensus(None, "values".to_string(), proposer.clone());
+    thread::sleep(Duration::from_secs(1));
+    client.consensus(None, "wabbit".to_string(), proposer.clone());
+    client.consensus(None, "wabb2it".to_string(), proposer.clone());
+    client.consensus(None, "wabitual".to_string(), proposer.clone());
+    client.consensus(Some(10), "wabitual".to_string(), proposer.clone());
+    thread::sleep(Duration::from_secs(1));
+    client.consensus(Some(10), "wabbit".to_string(), proposer.clone());
+    client.consensus(Some(10), "∑avingwabbit".to_string(), proposer);
+    thread::sleep(Duration::from_secs(3));
+
+    cluster.shutdown();
+    println!("storage: {:?}", storage.lock().unwrap());
+}
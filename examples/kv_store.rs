@@ -0,0 +1,16 @@
+use paxos::{Cluster, KvClient, KvStore};
+
+fn main() {
+    let (cluster, outputs) = Cluster::builder().proposers(2).learners(2).build_replicated(KvStore::new());
+    let alice = KvClient::new(0, cluster.proposer(0), outputs.clone());
+    let bob = KvClient::new(1, cluster.proposer(1), outputs);
+
+    println!("alice put x=1: {:?}", alice.put("x", "1"));
+    println!("bob get x: {:?}", bob.get("x"));
+    println!("bob cas x 1->2: {:?}", bob.compare_and_swap("x", Some("1"), Some("2")));
+    println!("alice cas x 1->3: {:?}", alice.compare_and_swap("x", Some("1"), Some("3")));
+    println!("alice delete x: {:?}", alice.delete("x"));
+    println!("bob get x: {:?}", bob.get("x"));
+
+    cluster.shutdown();
+}
@@ -0,0 +1,33 @@
+use paxos::model_check::ModelChecker;
+use std::env;
+use std::process;
+use std::time::Instant;
+
+/// Usage: model_check [proposers] [acceptors] [drops] [duplicates] [timeouts] [reorders]
+///
+/// Reorders are unbounded unless given.
+fn main() {
+    let args: Vec<u32> = env::args()
+        .skip(1)
+        .map(|arg| arg.parse().expect("arguments must be numbers"))
+        .collect();
+    let arg = |i: usize, default: u32| args.get(i).copied().unwrap_or(default);
+    let mut checker = ModelChecker::new()
+        .proposers(arg(0, 2) as usize)
+        .acceptors(arg(1, 3) as usize)
+        .drops(arg(2, 0))
+        .duplicates(arg(3, 0))
+        .timeouts(arg(4, 0));
+    if let Some(&reorders) = args.get(5) {
+        checker = checker.reorders(reorders);
+    }
+
+    let start = Instant::now();
+    match checker.run() {
+        Ok(states) => println!("explored {} states in {:?}, no violations", states, start.elapsed()),
+        Err(violation) => {
+            println!("{}", violation);
+            process::exit(1);
+        }
+    }
+}
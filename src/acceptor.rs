@@ -1,61 +1,393 @@
-use crossbeam_channel::{Receiver, Sender};
+use std::collections::BTreeMap;
+use std::io;
+use std::time::Instant;
+use crate::ballot::Ballot;
+use crate::failure_detector::FailureDetector;
+use crate::membership::{Configuration, Membership};
 use crate::message::Message;
-use crate::formatting::print_green;
+use crate::formatting::{log, print_green};
+use crate::transport::{NodeId, Transport};
+use crate::value::Value;
+use crate::wal::{AcceptorStorage, MemoryStorage};
 
-pub struct Acceptor {
+/// Acceptor state for a single log slot.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SlotState<V = String> {
+    /// Highest ballot promised for this slot.
+    pub max_id: Ballot,
+    /// Ballot and value most recently accepted for this slot.
+    pub accepted: Option<(Ballot, V)>,
+}
+
+impl<V> Default for SlotState<V> {
+    fn default() -> Self {
+        SlotState { max_id: Ballot::default(), accepted: None }
+    }
+}
+
+/// Everything an acceptor must remember across restarts.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AcceptorState<V = String> {
+    /// Highest ballot promised for every slot at once, by electing a leader.
+    pub promised: Ballot,
+    pub slots: BTreeMap<u64, SlotState<V>>,
+    /// Every slot below this one is decided and kept in a learner's
+    /// snapshot, so its state has been dropped.
+    pub truncated: u64,
+}
+
+impl<V> Default for AcceptorState<V> {
+    fn default() -> Self {
+        AcceptorState { promised: Ballot::default(), slots: BTreeMap::new(), truncated: 0 }
+    }
+}
+
+impl<V> AcceptorState<V> {
+    /// Highest ballot promised for `slot`, either on its own or by a leader.
+    pub fn max_id(&self, slot: u64) -> Ballot {
+        self.slots.get(&slot).map_or(self.promised, |state| state.max_id.max(self.promised))
+    }
+}
+
+pub struct Acceptor<V = String> {
     id: u64,
-    max_id: u64,
-    proposal_accepted: bool,
-    accepted_value: Option<String>,
-    accepted_proposal_number: Option<u64>,
-    round_number: u64,
+    state: AcceptorState<V>,
+    storage: Box<dyn AcceptorStorage<V>>,
+    learners: Vec<NodeId>,
+    /// Picks the learners of each slot instead of `learners`.
+    membership: Option<Membership>,
+    detector: Option<FailureDetector>,
 }
 
-impl Acceptor {
-    pub fn new(id: u64, max_id: u64) -> Self {
+impl<V: Value> Acceptor<V> {
+    /// An acceptor whose state lives only in memory.
+    pub fn new(id: u64) -> Self {
         Acceptor {
             id,
-            max_id,
-            proposal_accepted: false,
-            accepted_value: None,
-            accepted_proposal_number: None,
-            round_number: 0,
+            state: AcceptorState::default(),
+            storage: Box::new(MemoryStorage),
+            learners: vec![],
+            membership: None,
+            detector: None,
+        }
+    }
+
+    /// An acceptor that rebuilds its state from `storage` and persists every
+    /// change there before replying.
+    pub fn with_storage(id: u64, mut storage: Box<dyn AcceptorStorage<V>>) -> io::Result<Self> {
+        let state = storage.load()?;
+        Ok(Acceptor { id, state, storage, learners: vec![], membership: None, detector: None })
+    }
+
+    /// Every accepted value is also reported to `learners`.
+    pub fn with_learners(mut self, learners: Vec<NodeId>) -> Self {
+        self.learners = learners;
+        self
+    }
+
+    /// Reports each accepted value to the learners `membership` assigns to
+    /// its slot.
+    pub fn with_membership(mut self, membership: Membership) -> Self {
+        self.membership = Some(membership);
+        self
+    }
+
+    /// Sends heartbeats and watches peers through `detector`.
+    pub fn with_failure_detector(mut self, detector: FailureDetector) -> Self {
+        self.detector = Some(detector);
+        self
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn failure_detector(&self) -> Option<&FailureDetector> {
+        self.detector.as_ref()
+    }
+
+    /// Earliest time at which `tick` has work to do.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.detector.as_ref().map(FailureDetector::next_deadline)
+    }
+
+    pub fn tick(&mut self, transport: &dyn Transport<V>, now: Instant) {
+        if let Some(detector) = &mut self.detector {
+            detector.tick(None, transport, now);
+        }
+    }
+
+    pub fn handle_heartbeat(&mut self, from: u64, now: Instant) {
+        if let Some(detector) = &mut self.detector {
+            detector.heard_from(from, now);
         }
     }
 
-    pub fn handle_prepare(&mut self, proposal_number: u64, round_number: u64, value: String, tx: &Sender<Message>) {
-        if proposal_number <= self.max_id {
-            print_green(&format!("[Acceptor] PREPARE SEND FAIL: {:?}", Message::Fail(value.clone())));
-            tx.send(Message::Fail(value.clone())).unwrap();
+    /// State held for `slot`, if this acceptor has seen it.
+    pub fn slot(&self, slot: u64) -> Option<&SlotState<V>> {
+        self.state.slots.get(&slot)
+    }
+
+    pub fn state(&self) -> &AcceptorState<V> {
+        &self.state
+    }
+
+    /// Replies go to the proposer that owns `ballot`.
+    pub fn handle_prepare(&mut self, ballot: Ballot, slot: u64, value: V, transport: &dyn Transport<V>) {
+        if self.answer_truncated(ballot, slot, transport) {
+            return;
+        }
+        let max_id = self.state.max_id(slot);
+        let state = self.state.slots.entry(slot).or_default();
+        if ballot <= max_id {
+            let message = Message::Nack(ballot, slot, max_id, self.id);
+            print_green(&format!("[Acceptor] PREPARE SEND NACK: {:?}", message));
+            transport.broadcast(&[ballot.proposer_id], &message);
         } else {
-            self.max_id = proposal_number;
-            if self.proposal_accepted {
-                let message = Message::Promise(proposal_number, round_number, self.accepted_proposal_number, self.accepted_value.clone().unwrap());
-                print_green(&format!("[Acceptor] SEND ACCEPTED PROMISE: {:?}", message));
-                tx.send(message).unwrap();
-            } else {
-                let message = Message::Promise(proposal_number, round_number, None, value.clone());
-                print_green(&format!("[Acceptor] SEND PROMISE: {:?}", message));
-                tx.send(message).unwrap();
-            }
+            state.max_id = ballot;
+            self.storage.persist(slot, state).expect("[Acceptor] Failed to persist promise");
+            let message = match &state.accepted {
+                Some((accepted_ballot, accepted_value)) => {
+                    Message::Promise(ballot, slot, Some(*accepted_ballot), accepted_value.clone(), self.id)
+                }
+                None => Message::Promise(ballot, slot, None, value, self.id),
+            };
+            print_green(&format!("[Acceptor] SEND PROMISE: {:?}", message));
+            transport.broadcast(&[ballot.proposer_id], &message);
         }
     }
 
-    pub fn handle_propose(&mut self, proposal_number: u64, round_number: u64, value: String, tx: &Sender<Message>) {
-        if proposal_number >= self.max_id {
-            self.max_id = proposal_number;
-            self.proposal_accepted = true;
-            self.accepted_value = Some(value.clone());
-            self.accepted_proposal_number = Some(proposal_number);
-            if self.round_number < round_number {
-                self.accepted_proposal_number = None;
-                self.accepted_value = None;
-                self.proposal_accepted = false;
-                self.round_number = round_number;
+    /// Answers a learner's catch-up request with a vote for every value
+    /// accepted in `start..end`.
+    pub fn handle_catch_up(&self, from: NodeId, start: u64, end: u64, transport: &dyn Transport<V>) {
+        for (slot, state) in self.state.slots.range(start..end) {
+            if let Some((ballot, value)) = &state.accepted {
+                transport.broadcast(&[from], &Message::Accepted(*ballot, *slot, value.clone(), self.id));
             }
-            tx.send(Message::Accept(proposal_number, round_number, value.clone())).unwrap();
+        }
+    }
+
+    /// Sends client `from` everything this acceptor holds.
+    pub fn handle_inspect(&self, from: NodeId, transport: &dyn Transport<V>) {
+        let slots = self.state.slots.iter().map(|(slot, state)| (*slot, state.clone())).collect();
+        transport.broadcast(&[from], &Message::InspectReply(self.id, self.state.promised, self.state.truncated, slots));
+    }
+
+    /// Phase 1 for every slot from `from` onward at once, sent by a proposer
+    /// trying to become leader. The promise reports every value accepted in
+    /// those slots. It only covers slots from the truncation point onward,
+    /// which it says by starting there instead of at `from`.
+    pub fn handle_prepare_all(&mut self, ballot: Ballot, from: u64, transport: &dyn Transport<V>) {
+        let max_id = self.state.slots.range(from..).map(|(_, state)| state.max_id).fold(self.state.promised, Ballot::max);
+        if ballot <= max_id {
+            let message = Message::Nack(ballot, from, max_id, self.id);
+            print_green(&format!("[Acceptor] PREPARE ALL SEND NACK: {:?}", message));
+            transport.broadcast(&[ballot.proposer_id], &message);
+            return;
+        }
+        self.state.promised = ballot;
+        self.storage.persist_promise(ballot).expect("[Acceptor] Failed to persist promise");
+        let from = from.max(self.state.truncated);
+        let accepted = self
+            .state
+            .slots
+            .range(from..)
+            .filter_map(|(slot, state)| state.accepted.clone().map(|(ballot, value)| (*slot, ballot, value)))
+            .collect();
+        let message = Message::PromiseAll(ballot, from, accepted, self.id);
+        print_green(&format!("[Acceptor] SEND PROMISE ALL: {:?}", message));
+        transport.broadcast(&[ballot.proposer_id], &message);
+    }
+
+    /// Adopts the configurations a learner has seen decided.
+    pub fn handle_configure(&mut self, known: u64, changes: Vec<(u64, Configuration)>) {
+        if let Some(membership) = &mut self.membership {
+            membership.merge(known, changes);
+        }
+    }
+
+    pub fn handle_propose(&mut self, ballot: Ballot, slot: u64, value: V, transport: &dyn Transport<V>) {
+        if self.answer_truncated(ballot, slot, transport) {
+            return;
+        }
+        let max_id = self.state.max_id(slot);
+        let state = self.state.slots.entry(slot).or_default();
+        if ballot >= max_id {
+            state.max_id = ballot;
+            state.accepted = Some((ballot, value.clone()));
+            self.storage.persist(slot, state).expect("[Acceptor] Failed to persist accept");
+            transport.broadcast(&[ballot.proposer_id], &Message::Accept(ballot, slot, value.clone(), self.id));
+            let learners = self.membership.as_ref().map_or(&self.learners, |membership| &membership.config(slot).learners);
+            transport.broadcast(learners, &Message::Accepted(ballot, slot, value, self.id));
         } else {
-            tx.send(Message::Fail(value.clone())).unwrap();
+            transport.broadcast(&[ballot.proposer_id], &Message::Nack(ballot, slot, max_id, self.id));
+        }
+    }
+
+    /// Drops the state of every slot below `index`, which a learner has
+    /// compacted into a snapshot. Those slots are decided, so requests for
+    /// them are answered with `Compact` from now on, which sends the proposer
+    /// to a later slot, rather than with a promise that lacks the value this
+    /// acceptor may have accepted.
+    pub fn handle_compact(&mut self, index: u64) {
+        if index <= self.state.truncated {
+            return;
+        }
+        self.state.slots = self.state.slots.split_off(&index);
+        self.state.truncated = index;
+        self.storage.compact(&self.state).expect("[Acceptor] Failed to compact storage");
+    }
+
+    /// Tells the proposer that owns `ballot` when `slot` has been compacted
+    /// away. Returns whether it was.
+    fn answer_truncated(&self, ballot: Ballot, slot: u64, transport: &dyn Transport<V>) -> bool {
+        if slot < self.state.truncated {
+            log!("[Acceptor] Slot {} is compacted", slot);
+            transport.broadcast(&[ballot.proposer_id], &Message::Compact(self.state.truncated));
+            return true;
         }
+        false
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::tests::drain;
+    use crate::transport::ChannelTransport;
+    use crate::wal::FileStorage;
+
+    #[test]
+    fn test_slots_are_independent() {
+        let nodes: Vec<ChannelTransport> = ChannelTransport::network(&[0, 1, 2]);
+        let mut acceptor = Acceptor::new(2);
+
+        acceptor.handle_prepare(Ballot::new(5, 0), 0, "a".to_string(), &nodes[2]);
+        acceptor.handle_propose(Ballot::new(5, 0), 0, "a".to_string(), &nodes[2]);
+        // A lower ballot is still fine for a slot that has not promised anything.
+        acceptor.handle_prepare(Ballot::new(1, 0), 1, "b".to_string(), &nodes[2]);
+
+        let mut replies = drain(&nodes[0]).into_iter();
+        assert!(matches!(replies.next().unwrap(), Message::Promise(_, 0, None, _, _)));
+        assert!(matches!(replies.next().unwrap(), Message::Accept(_, 0, _, _)));
+        assert!(matches!(replies.next().unwrap(), Message::Promise(_, 1, None, _, _)));
+        assert_eq!(acceptor.slot(0).unwrap().accepted, Some((Ballot::new(5, 0), "a".to_string())));
+        assert_eq!(acceptor.slot(1).unwrap().accepted, None);
+    }
+
+    #[test]
+    fn test_promise_reports_accepted_value() {
+        let nodes: Vec<ChannelTransport> = ChannelTransport::network(&[0, 1, 2]);
+        let mut acceptor = Acceptor::new(2);
+
+        acceptor.handle_propose(Ballot::new(3, 0), 0, "a".to_string(), &nodes[2]);
+        acceptor.handle_prepare(Ballot::new(4, 0), 0, "b".to_string(), &nodes[2]);
+        acceptor.handle_prepare(Ballot::new(2, 0), 0, "c".to_string(), &nodes[2]);
+
+        let mut replies = drain(&nodes[0]).into_iter();
+        assert!(matches!(replies.next().unwrap(), Message::Accept(_, 0, _, _)));
+        match replies.next().unwrap() {
+            Message::Promise(ballot, 0, Some(accepted), value, _) => {
+                assert_eq!(ballot, Ballot::new(4, 0));
+                assert_eq!(accepted, Ballot::new(3, 0));
+                assert_eq!(value, "a");
+            }
+            message => panic!("unexpected {:?}", message),
+        }
+        match replies.next().unwrap() {
+            Message::Nack(ballot, 0, max_id, 2) => {
+                assert_eq!(ballot, Ballot::new(2, 0));
+                assert_eq!(max_id, Ballot::new(4, 0));
+            }
+            message => panic!("unexpected {:?}", message),
+        }
+    }
+
+    #[test]
+    fn test_equal_rounds_from_different_proposers_are_ordered() {
+        let nodes: Vec<ChannelTransport> = ChannelTransport::network(&[0, 1, 2]);
+        let mut acceptor = Acceptor::new(2);
+
+        acceptor.handle_prepare(Ballot::new(1, 1), 0, "a".to_string(), &nodes[2]);
+        acceptor.handle_prepare(Ballot::new(1, 0), 0, "b".to_string(), &nodes[2]);
+        acceptor.handle_propose(Ballot::new(1, 0), 0, "b".to_string(), &nodes[2]);
+
+        assert!(matches!(drain(&nodes[1])[..], [Message::Promise(..)]));
+        assert!(matches!(drain(&nodes[0])[..], [Message::Nack(..), Message::Nack(..)]));
+        assert_eq!(acceptor.slot(0).unwrap().max_id, Ballot::new(1, 1));
+    }
+
+    #[test]
+    fn test_prepare_all_covers_future_slots() {
+        let nodes: Vec<ChannelTransport> = ChannelTransport::network(&[0, 1, 2]);
+        let mut acceptor = Acceptor::new(2);
+
+        acceptor.handle_propose(Ballot::new(1, 1), 0, "a".to_string(), &nodes[2]);
+        acceptor.handle_propose(Ballot::new(1, 1), 3, "b".to_string(), &nodes[2]);
+        acceptor.handle_prepare_all(Ballot::new(2, 0), 1, &nodes[2]);
+        acceptor.handle_propose(Ballot::new(1, 1), 5, "c".to_string(), &nodes[2]);
+        acceptor.handle_prepare(Ballot::new(2, 1), 7, "d".to_string(), &nodes[2]);
+        acceptor.handle_prepare_all(Ballot::new(1, 0), 0, &nodes[2]);
+
+        let replies = drain(&nodes[0]);
+        assert_eq!(replies, vec![
+            Message::PromiseAll(Ballot::new(2, 0), 1, vec![(3, Ballot::new(1, 1), "b".to_string())], 2),
+            Message::Nack(Ballot::new(1, 0), 0, Ballot::new(2, 1), 2),
+        ]);
+        let replies = drain(&nodes[1]);
+        assert!(matches!(replies[..], [Message::Accept(..), Message::Accept(..), Message::Nack(_, 5, _, 2), Message::Promise(_, 7, None, _, _)]));
+    }
+
+    #[test]
+    fn test_restarted_acceptor_keeps_promises() {
+        let path = std::env::temp_dir().join(format!("paxos-acceptor-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let nodes: Vec<ChannelTransport> = ChannelTransport::network(&[0, 1, 2]);
+
+        let mut acceptor = Acceptor::with_storage(2, Box::new(FileStorage::open(&path).unwrap())).unwrap();
+        acceptor.handle_prepare(Ballot::new(2, 0), 0, "a".to_string(), &nodes[2]);
+        acceptor.handle_propose(Ballot::new(2, 0), 0, "a".to_string(), &nodes[2]);
+        drop(acceptor);
+
+        let mut acceptor = Acceptor::with_storage(2, Box::new(FileStorage::open(&path).unwrap())).unwrap();
+        acceptor.handle_prepare(Ballot::new(1, 1), 0, "b".to_string(), &nodes[2]);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(drain(&nodes[0])[..], [Message::Promise(..), Message::Accept(..)]));
+        assert!(matches!(drain(&nodes[1])[..], [Message::Nack(..)]));
+        assert_eq!(acceptor.slot(0).unwrap().accepted, Some((Ballot::new(2, 0), "a".to_string())));
+    }
+
+    #[test]
+    fn test_compacted_slots_are_forgotten() {
+        let path = std::env::temp_dir().join(format!("paxos-acceptor-compact-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let nodes: Vec<ChannelTransport> = ChannelTransport::network(&[0, 1, 2]);
+        let ballot = Ballot::new(1, 0);
+
+        let mut acceptor = Acceptor::with_storage(2, Box::new(FileStorage::open(&path).unwrap())).unwrap();
+        for slot in 0..3 {
+            acceptor.handle_propose(ballot, slot, slot.to_string(), &nodes[2]);
+        }
+        acceptor.handle_compact(2);
+        drop(acceptor);
+
+        let mut acceptor = Acceptor::with_storage(2, Box::new(FileStorage::open(&path).unwrap())).unwrap();
+        drain(&nodes[0]);
+        acceptor.handle_prepare(Ballot::new(2, 0), 1, "b".to_string(), &nodes[2]);
+        acceptor.handle_propose(Ballot::new(2, 0), 0, "b".to_string(), &nodes[2]);
+        acceptor.handle_prepare_all(Ballot::new(3, 0), 0, &nodes[2]);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(acceptor.state().truncated, 2);
+        assert_eq!(acceptor.state().slots.keys().collect::<Vec<_>>(), [&2]);
+        assert_eq!(
+            drain(&nodes[0]),
+            vec![
+                Message::Compact(2),
+                Message::Compact(2),
+                Message::PromiseAll(Ballot::new(3, 0), 2, vec![(2, ballot, "2".to_string())], 2),
+            ]
+        );
+    }
+}
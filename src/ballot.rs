@@ -0,0 +1,41 @@
+use std::fmt;
+
+/// A proposal number that is unique across proposers.
+///
+/// Ballots are ordered by `round` first and `proposer_id` second, so two
+/// proposers can never produce the same ballot.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ballot {
+    pub round: u64,
+    pub proposer_id: u64,
+}
+
+impl Ballot {
+    pub fn new(round: u64, proposer_id: u64) -> Self {
+        Ballot { round, proposer_id }
+    }
+
+    /// The smallest ballot owned by `proposer_id` that is greater than `self`.
+    pub fn next(&self, proposer_id: u64) -> Self {
+        Ballot::new(self.round + 1, proposer_id)
+    }
+}
+
+impl fmt::Display for Ballot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.round, self.proposer_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ballots_are_totally_ordered() {
+        assert!(Ballot::new(1, 0) < Ballot::new(1, 1));
+        assert!(Ballot::new(1, 5) < Ballot::new(2, 0));
+        assert_ne!(Ballot::new(2, 0), Ballot::new(2, 1));
+        assert!(Ballot::new(3, 7).next(0) > Ballot::new(3, 7));
+    }
+}
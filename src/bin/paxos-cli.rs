@@ -0,0 +1,197 @@
+//! Interactive client of a running TCP cluster: submits values, waits for
+//! their slot and dumps the state of acceptors and learners.
+//!
+//! Needs a `client` node in the manifest for the nodes to answer, see
+//! `paxos::manifest`. Type `help` for the commands.
+use clap::Parser;
+use paxos::manifest::{Manifest, ManifestError, Role};
+use paxos::remote::{RemoteClient, RemoteError};
+use paxos::NodeId;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::{Context, Editor, Helper, Highlighter, Hinter, Validator};
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+
+const COMMANDS: [&str; 7] = ["propose", "acceptor", "acceptors", "learner", "nodes", "help", "quit"];
+
+const HELP: &str = "\
+propose <value>    submit a value and wait for the slot it is decided in
+acceptor <id>      show what an acceptor has promised and accepted
+acceptors          the same for every acceptor
+learner [<id>]     show a learner's decided log
+nodes              list the nodes of the manifest
+quit               leave";
+
+#[derive(Parser)]
+#[command(about = "Interactive client of a Paxos cluster")]
+struct Cli {
+    /// Cluster manifest.
+    #[arg(long, default_value = "cluster.toml")]
+    manifest: PathBuf,
+    /// Client node of the manifest to listen as; the first one by default.
+    #[arg(long)]
+    id: Option<NodeId>,
+    /// Proposer to submit values to; the first one by default.
+    #[arg(long)]
+    proposer: Option<NodeId>,
+    /// Learner to learn decisions from; the first one by default.
+    #[arg(long)]
+    learner: Option<NodeId>,
+    /// Seconds to wait for a value to be decided or a node to answer.
+    #[arg(long, default_value_t = 5)]
+    timeout: u64,
+    /// File to keep the command history in.
+    #[arg(long)]
+    history: Option<PathBuf>,
+}
+
+/// Completes command names, and node ids after `acceptor` and `learner`.
+#[derive(Helper, Hinter, Highlighter, Validator)]
+struct CliHelper {
+    acceptors: Vec<String>,
+    learners: Vec<String>,
+}
+
+impl Completer for CliHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let line = &line[..pos];
+        let start = line.rfind(' ').map_or(0, |space| space + 1);
+        let word = &line[start..];
+        let candidates: Vec<&str> = match line[..start].split_whitespace().collect::<Vec<_>>()[..] {
+            [] => COMMANDS.to_vec(),
+            ["acceptor"] => self.acceptors.iter().map(String::as_str).collect(),
+            ["learner"] => self.learners.iter().map(String::as_str).collect(),
+            _ => vec![],
+        };
+        let matches = candidates.into_iter().filter(|candidate| candidate.starts_with(word)).map(str::to_string).collect();
+        Ok((start, matches))
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let timeout = Duration::from_secs(cli.timeout);
+    let mut client = match connect(&cli) {
+        Ok(client) => client,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            process::exit(1);
+        }
+    };
+    let ids = |role| client.manifest().ids(role).iter().map(|id| id.to_string()).collect();
+    let helper = CliHelper { acceptors: ids(Role::Acceptor), learners: ids(Role::Learner) };
+    let mut editor: Editor<CliHelper, DefaultHistory> = Editor::new().expect("Failed to open the terminal");
+    editor.set_helper(Some(helper));
+    let history = cli.history.clone().or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".paxos_history")));
+    if let Some(history) = &history {
+        let _ = editor.load_history(history);
+    }
+
+    loop {
+        let line = match editor.readline("paxos> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+            Err(error) => {
+                eprintln!("error: {}", error);
+                break;
+            }
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line);
+        match run(&mut client, line, timeout) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(error) => eprintln!("error: {}", error),
+        }
+    }
+    if let Some(history) = &history {
+        let _ = editor.save_history(history);
+    }
+}
+
+fn connect(cli: &Cli) -> Result<RemoteClient, RemoteError> {
+    let manifest = Manifest::load(&cli.manifest)?;
+    let id = match cli.id {
+        Some(id) => id,
+        None => *manifest.ids(Role::Client).first().ok_or(ManifestError::MissingRole(Role::Client))?,
+    };
+    let mut client = RemoteClient::connect(manifest, id)?;
+    if let Some(proposer) = cli.proposer {
+        client = client.with_proposer(proposer)?;
+    }
+    if let Some(learner) = cli.learner {
+        client = client.with_learner(learner)?;
+    }
+    Ok(client)
+}
+
+/// Runs one command line. Returns `false` once the REPL should stop.
+fn run(client: &mut RemoteClient, line: &str, timeout: Duration) -> Result<bool, RemoteError> {
+    let (command, argument) = line.split_once(' ').map_or((line, ""), |(command, argument)| (command, argument.trim()));
+    match (command, argument) {
+        ("propose", value) if !value.is_empty() => {
+            let slot = client.submit(value.to_string(), timeout)?;
+            println!("decided in slot {}", slot);
+        }
+        ("acceptor", id) => match id.parse() {
+            Ok(id) => print_acceptor(client, id, timeout)?,
+            Err(_) => println!("usage: acceptor <id>"),
+        },
+        ("acceptors", "") => {
+            for id in client.manifest().ids(Role::Acceptor) {
+                print_acceptor(client, id, timeout)?;
+            }
+        }
+        ("learner", id) => {
+            let id = match id {
+                "" => client.manifest().ids(Role::Learner)[0],
+                id => match id.parse() {
+                    Ok(id) => id,
+                    Err(_) => {
+                        println!("usage: learner [<id>]");
+                        return Ok(true);
+                    }
+                },
+            };
+            print_learner(client, id)?;
+        }
+        ("nodes", "") => {
+            for node in &client.manifest().nodes {
+                println!("{:>4}  {:<8}  {}", node.id, node.role.to_string(), node.address);
+            }
+        }
+        ("help", "") => println!("{}", HELP),
+        ("quit" | "exit", "") => return Ok(false),
+        _ => println!("unknown command, try `help`"),
+    }
+    Ok(true)
+}
+
+fn print_acceptor(client: &RemoteClient, id: NodeId, timeout: Duration) -> Result<(), RemoteError> {
+    let state = client.acceptor_state(id, timeout)?;
+    println!("acceptor {}: promised {} for every slot, dropped slots below {}", id, state.promised, state.truncated);
+    for (slot, state) in &state.slots {
+        match &state.accepted {
+            Some((ballot, value)) => println!("  slot {}: promised {}, accepted {:?} at {}", slot, state.max_id, value, ballot),
+            None => println!("  slot {}: promised {}", slot, state.max_id),
+        }
+    }
+    Ok(())
+}
+
+fn print_learner(client: &RemoteClient, id: NodeId) -> Result<(), RemoteError> {
+    let log = client.learner_log(id)?;
+    println!("learner {}: {} slots decided, snapshot of slots below {}", id, log.len(), log.first());
+    for (slot, value) in log.iter() {
+        println!("  slot {}: {:?}", slot, value);
+    }
+    Ok(())
+}
@@ -1,19 +1,59 @@
-use crossbeam_channel::{bounded, Receiver, Sender};
+use crossbeam_channel::Sender;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
 use crate::message::Message;
 use crate::formatting::print_green;
+use crate::history::Recorder;
+use crate::state_machine::{Outputs, Request};
+use crate::value::Value;
 
-pub struct Client {
+pub struct Client<V = String> {
     id: u64,
+    recorder: Option<Recorder<V>>,
+    /// Sequence number of the next request sent through `execute`.
+    seq: AtomicU64,
 }
 
-impl Client {
+impl<V: Value> Client<V> {
     pub fn new(id: u64) -> Self {
-        Client { id }
+        Client { id, recorder: None, seq: AtomicU64::new(0) }
     }
 
-    pub fn consensus(&self, id: Option<u64>, value: String, tx: Sender<Message>) {
-        let message = Message::Consensus(id.unwrap_or(0), value);
+    /// Records every value this client submits in `recorder`.
+    pub fn with_recorder(mut self, recorder: Recorder<V>) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn consensus(&self, id: Option<u64>, value: V, tx: Sender<Message<V>>) {
+        let message = Message::Consensus(id.unwrap_or(0), value.clone());
         print_green(&format!("[Client] CONSENSUS: {:?}", message));
-        tx.send(message.clone()).unwrap_or(println!("Failed to send message {:?}", message.clone()));
+        if let Some(recorder) = &self.recorder {
+            recorder.invoke(self.id, value.clone());
+        }
+        if tx.send(message.clone()).is_err() {
+            println!("Failed to send message {:?}", message);
+        }
+    }
+}
+
+impl<C: Value> Client<Request<C>> {
+    /// Submits `command` to a replicated state machine and waits up to
+    /// `timeout` for the output of applying it. Returns `None` if the command
+    /// was not applied in time.
+    pub fn execute<O: Clone>(
+        &self,
+        command: C,
+        proposer: Sender<Message<Request<C>>>,
+        outputs: &Outputs<O>,
+        timeout: Duration,
+    ) -> Option<O> {
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        self.consensus(None, Request { client: self.id, seq, command }, proposer);
+        outputs.wait(self.id, seq, timeout).map(|(_, output)| output)
     }
 }
@@ -0,0 +1,518 @@
+use crossbeam_channel::Sender;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::acceptor::Acceptor;
+use crate::failure_detector::{FailureDetector, HeartbeatConfig, Suspicions};
+use crate::fault::{FaultConfig, FaultHandle, FaultyTransport};
+use crate::learner::{Learner, Storage};
+use crate::membership::{Configuration, Membership};
+use crate::message::Message;
+use crate::node::{run_acceptor, run_learner, run_proposer};
+use crate::proposer::{Proposer, RetryConfig};
+use crate::quorum::{QuorumSystem, Quorums};
+use crate::state_machine::{Outputs, Request, StateMachine};
+use crate::transport::{Network, NodeId, Transport};
+use crate::value::Value;
+use crate::wal::FileStorage;
+
+/// Configures and spawns a `Cluster`.
+///
+/// Defaults to one proposer, three acceptors and one learner. Node ids are
+/// assigned in that order starting from 0, so proposer `i` has id `i`.
+#[derive(Clone)]
+pub struct ClusterBuilder {
+    proposers: usize,
+    acceptors: usize,
+    learners: usize,
+    retry: RetryConfig,
+    storage_dir: Option<PathBuf>,
+    faults: Option<FaultConfig>,
+    heartbeats: HeartbeatConfig,
+    catch_up: Duration,
+    snapshot_interval: Option<u64>,
+    alpha: Option<u64>,
+    quorums: Quorums,
+}
+
+impl Default for ClusterBuilder {
+    fn default() -> Self {
+        ClusterBuilder {
+            proposers: 1,
+            acceptors: 3,
+            learners: 1,
+            retry: RetryConfig::default(),
+            storage_dir: None,
+            faults: None,
+            heartbeats: HeartbeatConfig::default(),
+            catch_up: Duration::from_secs(1),
+            snapshot_interval: None,
+            alpha: None,
+            quorums: Quorums::default(),
+        }
+    }
+}
+
+impl ClusterBuilder {
+    pub fn proposers(mut self, proposers: usize) -> Self {
+        self.proposers = proposers;
+        self
+    }
+
+    pub fn acceptors(mut self, acceptors: usize) -> Self {
+        self.acceptors = acceptors;
+        self
+    }
+
+    pub fn learners(mut self, learners: usize) -> Self {
+        self.learners = learners;
+        self
+    }
+
+    /// Timeouts and backoff used by every proposer.
+    pub fn retry(mut self, retry: RetryConfig) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Directory for the acceptors' write-ahead logs. Without one, acceptor
+    /// state is kept in memory only.
+    pub fn storage_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.storage_dir = Some(dir.into());
+        self
+    }
+
+    /// Injects `faults` into every message sent between nodes. Client
+    /// requests and `Terminate` are always delivered.
+    pub fn faults(mut self, faults: FaultConfig) -> Self {
+        self.faults = Some(faults);
+        self
+    }
+
+    /// How often nodes send heartbeats and how long before a silent node is
+    /// suspected.
+    pub fn heartbeats(mut self, heartbeats: HeartbeatConfig) -> Self {
+        self.heartbeats = heartbeats;
+        self
+    }
+
+    /// How often learners ask each other and the acceptors for decisions
+    /// they missed.
+    pub fn catch_up(mut self, interval: Duration) -> Self {
+        self.catch_up = interval;
+        self
+    }
+
+    /// Snapshot the state machine of a replicated cluster every `slots`
+    /// decided slots and discard the log below the snapshot.
+    pub fn snapshot_interval(mut self, slots: u64) -> Self {
+        self.snapshot_interval = Some(slots);
+        self
+    }
+
+    /// Lets `Command::Reconfigure` values change the acceptors and learners,
+    /// each change taking over `alpha` slots after the one it is decided in.
+    /// Proposers never run more than `alpha` slots ahead of the decided log.
+    pub fn alpha(mut self, alpha: u64) -> Self {
+        self.alpha = Some(alpha);
+        self
+    }
+
+    /// Forms the quorums of proposers and learners with `system` instead of
+    /// majorities.
+    pub fn quorum_system(mut self, system: impl QuorumSystem + 'static) -> Self {
+        self.quorums = Quorums::new(system);
+        self
+    }
+
+    /// Spawns one thread per node and wires them together with channels.
+    /// The nodes agree on values of type `V`.
+    pub fn build<V: Value>(self) -> Cluster<V> {
+        self.spawn(|learner| learner)
+    }
+
+    /// Like `build`, but the learners apply every decided request to
+    /// `machine`, which they share along with the log. Clients find the
+    /// result of each request in the returned `Outputs`.
+    pub fn build_replicated<C: Value, S: StateMachine<C>>(self, machine: S) -> (Cluster<Request<C>>, Outputs<S::Output>) {
+        let machine = Arc::new(Mutex::new(machine));
+        let outputs = Outputs::new();
+        let snapshot_interval = self.snapshot_interval;
+        let learner_outputs = outputs.clone();
+        let cluster = self.spawn(move |learner| {
+            let learner = learner.with_state_machine(machine.clone(), learner_outputs.clone());
+            match snapshot_interval {
+                Some(slots) => learner.with_snapshot_interval(slots),
+                None => learner,
+            }
+        });
+        (cluster, outputs)
+    }
+
+    /// Spawns the nodes, letting `learner` finish configuring each learner.
+    fn spawn<V: Value>(self, learner: impl Fn(Learner<V>) -> Learner<V> + Send + Sync + 'static) -> Cluster<V> {
+        let proposer_ids: Vec<NodeId> = (0..self.proposers as u64).collect();
+        let acceptor_ids: Vec<NodeId> = (0..self.acceptors as u64).map(|i| i + self.proposers as u64).collect();
+        let learner_ids: Vec<NodeId> = (0..self.learners as u64).map(|i| i + (self.proposers + self.acceptors) as u64).collect();
+        let ids: Vec<NodeId> = proposer_ids.iter().chain(&acceptor_ids).chain(&learner_ids).copied().collect();
+        let membership = self.alpha.map(|alpha| {
+            Membership::new(Configuration::new(acceptor_ids.clone(), learner_ids.clone()))
+                .with_alpha(alpha)
+                .with_quorum_system(self.quorums.clone())
+        });
+        let mut cluster = Cluster {
+            network: Network::new(),
+            faults: self.faults.clone().map(FaultHandle::new),
+            ids: ids.clone(),
+            proposer_ids: proposer_ids.clone(),
+            acceptor_ids: acceptor_ids.clone(),
+            learner_ids: learner_ids.clone(),
+            proposers: vec![],
+            acceptors: vec![],
+            learners: vec![],
+            storage: Storage::default(),
+            suspicions: Suspicions::new(),
+            start: Instant::now(),
+            membership,
+            learner: Box::new(learner),
+            builder: self,
+        };
+        let transports: Vec<_> = ids.iter().map(|id| cluster.transport(*id)).collect();
+        let mut transports = transports.into_iter();
+        for id in &proposer_ids {
+            let mut proposer = Proposer::new(*id, acceptor_ids.clone())
+                .with_quorum_system(cluster.builder.quorums.clone())
+                .with_retry_config(cluster.builder.retry.clone())
+                .with_failure_detector(cluster.detector(*id));
+            if let Some(membership) = &cluster.membership {
+                proposer = proposer.with_membership(membership.clone());
+            }
+            let transport = transports.next().unwrap();
+            cluster.proposers.push(thread::spawn(move || run_proposer(proposer, transport)));
+        }
+        for id in &acceptor_ids {
+            cluster.spawn_acceptor(*id, transports.next().unwrap());
+        }
+        for id in &learner_ids {
+            cluster.spawn_learner(*id, transports.next().unwrap());
+        }
+        cluster
+    }
+}
+
+/// A running set of proposer, acceptor and learner threads.
+pub struct Cluster<V = String> {
+    network: Network<V>,
+    faults: Option<FaultHandle>,
+    /// Every node ever started, in order of id.
+    ids: Vec<NodeId>,
+    proposer_ids: Vec<NodeId>,
+    acceptor_ids: Vec<NodeId>,
+    learner_ids: Vec<NodeId>,
+    proposers: Vec<thread::JoinHandle<()>>,
+    acceptors: Vec<thread::JoinHandle<()>>,
+    learners: Vec<thread::JoinHandle<()>>,
+    storage: Storage<V>,
+    suspicions: Suspicions,
+    start: Instant,
+    /// Membership every node starts with, if the cluster can reconfigure.
+    membership: Option<Membership>,
+    learner: Box<dyn Fn(Learner<V>) -> Learner<V> + Send + Sync>,
+    builder: ClusterBuilder,
+}
+
+impl Cluster {
+    pub fn builder() -> ClusterBuilder {
+        ClusterBuilder::default()
+    }
+}
+
+impl<V: Value> Cluster<V> {
+
+    /// Channel used by clients to reach proposer `id`.
+    pub fn proposer(&self, id: usize) -> Sender<Message<V>> {
+        self.network.sender(id as NodeId).unwrap()
+    }
+
+    /// Controls the injected faults, if the cluster was built with any.
+    pub fn faults(&self) -> Option<FaultHandle> {
+        self.faults.clone()
+    }
+
+    /// Values recorded by the learners.
+    pub fn storage(&self) -> Storage<V> {
+        self.storage.clone()
+    }
+
+    /// Peers each node currently suspects of having failed.
+    pub fn suspicions(&self) -> Suspicions {
+        self.suspicions.clone()
+    }
+
+    /// Starts a new acceptor and returns its id. It only votes once a
+    /// `Command::Reconfigure` including it takes over.
+    pub fn add_acceptor(&mut self) -> NodeId {
+        let id = self.next_id();
+        let transport = self.transport(id);
+        self.acceptor_ids.push(id);
+        self.spawn_acceptor(id, transport);
+        id
+    }
+
+    /// Starts a new learner, sharing the log of the others, and returns its
+    /// id. Acceptors report to it once a `Command::Reconfigure` including it
+    /// takes over.
+    pub fn add_learner(&mut self) -> NodeId {
+        let id = self.next_id();
+        let transport = self.transport(id);
+        self.learner_ids.push(id);
+        self.spawn_learner(id, transport);
+        id
+    }
+
+    /// Stops node `id` as if it had crashed; the rest keep running.
+    pub fn stop(&self, id: NodeId) {
+        if let Some(sender) = self.network.sender(id) {
+            let _ = sender.send(Message::Terminate);
+        }
+    }
+
+    /// Sends `Terminate` to every node and waits for its thread to exit.
+    pub fn shutdown(self) {
+        for id in &self.ids {
+            if self.network.sender(*id).unwrap().send(Message::Terminate).is_err() {
+                println!("Failed to send TERMINATE message");
+            }
+        }
+        for handle in self.proposers.into_iter().chain(self.acceptors).chain(self.learners) {
+            handle.join().unwrap();
+        }
+    }
+
+    fn next_id(&mut self) -> NodeId {
+        let id = self.ids.last().map_or(0, |id| id + 1);
+        self.ids.push(id);
+        id
+    }
+
+    /// Joins node `id` to the network, behind the injected faults if any.
+    fn transport(&self, id: NodeId) -> Box<dyn Transport<V>> {
+        let transport = self.network.join(id);
+        match &self.faults {
+            Some(faults) => Box::new(FaultyTransport::new(transport, faults.clone(), id)),
+            None => Box::new(transport),
+        }
+    }
+
+    fn detector(&self, id: NodeId) -> FailureDetector {
+        FailureDetector::new(id, self.ids.clone(), self.builder.heartbeats.clone(), self.start)
+            .with_report(self.suspicions.clone())
+    }
+
+    fn spawn_acceptor(&mut self, id: NodeId, transport: Box<dyn Transport<V>>) {
+        let mut acceptor = self.acceptor(id).with_learners(self.learner_ids.clone()).with_failure_detector(self.detector(id));
+        if let Some(membership) = &self.membership {
+            acceptor = acceptor.with_membership(membership.clone());
+        }
+        self.acceptors.push(thread::spawn(move || run_acceptor(acceptor, transport)));
+    }
+
+    fn spawn_learner(&mut self, id: NodeId, transport: Box<dyn Transport<V>>) {
+        let mut learner = Learner::new(id, self.proposer_ids.clone(), self.acceptor_ids.clone())
+            .with_quorum_system(self.builder.quorums.clone())
+            .with_catch_up(self.learner_ids.clone(), self.builder.catch_up, self.start)
+            .with_failure_detector(self.detector(id));
+        if let Some(membership) = &self.membership {
+            learner = learner.with_membership(membership.clone());
+        }
+        let learner = (self.learner)(learner);
+        let storage = self.storage.clone();
+        self.learners.push(thread::spawn(move || run_learner(learner, transport, storage)));
+    }
+
+    fn acceptor(&self, id: u64) -> Acceptor<V> {
+        let Some(dir) = &self.builder.storage_dir else { return Acceptor::new(id) };
+        std::fs::create_dir_all(dir).expect("Failed to create storage directory");
+        let storage = FileStorage::open(dir.join(format!("acceptor-{}.wal", id))).expect("Failed to open acceptor log");
+        Acceptor::with_storage(id, Box::new(storage)).expect("Failed to recover acceptor state")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::Client;
+    use crate::codec::CodecError;
+    use crate::membership::Command;
+    use crate::quorum::Flexible;
+
+    /// Polls `done` until it holds, for up to five seconds. Returns whether
+    /// it did.
+    fn wait_until(mut done: impl FnMut() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done() {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+
+    #[test]
+    fn test_propose_single_value() {
+        let client = Client::new(0);
+        let cluster = Cluster::builder().build();
+        let storage = cluster.storage();
+
+        client.consensus(None, "values".to_string(), cluster.proposer(0));
+        wait_until(|| storage.lock().unwrap().len() == 1);
+        cluster.shutdown();
+
+        println!("storage: {:?}", storage.lock().unwrap());
+        assert_eq!(storage.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_competing_proposers_both_decide() {
+        let client = Client::new(0);
+        let cluster = Cluster::builder().proposers(2).build();
+        let storage = cluster.storage();
+
+        client.consensus(None, "a".to_string(), cluster.proposer(0));
+        client.consensus(None, "b".to_string(), cluster.proposer(1));
+        let decided = |value: &str| storage.lock().unwrap().iter().any(|(_, decided)| decided == value);
+        let both = wait_until(|| decided("a") && decided("b"));
+        cluster.shutdown();
+
+        assert!(both);
+    }
+
+    #[test]
+    fn test_binary_values_are_decided() {
+        let client = Client::new(0);
+        let cluster = Cluster::builder().build();
+        let storage = cluster.storage();
+
+        client.consensus(None, vec![0u8, 0xff], cluster.proposer(0));
+        wait_until(|| storage.lock().unwrap().len() == 1);
+        cluster.shutdown();
+
+        assert_eq!(storage.lock().unwrap().entries(), [(0, vec![0, 0xff])]);
+    }
+
+    /// Numbers the commands it applies.
+    struct Counter(u64);
+
+    impl StateMachine<String> for Counter {
+        type Output = (u64, String);
+
+        fn apply(&mut self, _slot: u64, command: &String) -> (u64, String) {
+            self.0 += 1;
+            (self.0, command.clone())
+        }
+
+        fn snapshot(&self) -> Vec<u8> {
+            self.0.to_le_bytes().to_vec()
+        }
+
+        fn restore(&mut self, state: &[u8]) -> Result<(), CodecError> {
+            self.0 = u64::from_le_bytes(state.try_into().map_err(|_| CodecError::Truncated)?);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_clients_get_state_machine_outputs() {
+        let (cluster, outputs) = Cluster::builder().learners(2).build_replicated(Counter(0));
+        let client = Client::new(0);
+        let timeout = Duration::from_secs(5);
+
+        let first = client.execute("a".to_string(), cluster.proposer(0), &outputs, timeout);
+        let second = client.execute("a".to_string(), cluster.proposer(0), &outputs, timeout);
+        let storage = cluster.storage();
+        cluster.shutdown();
+
+        assert_eq!(first, Some((1, "a".to_string())));
+        assert_eq!(second, Some((2, "a".to_string())));
+        assert_eq!(storage.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_snapshots_compact_the_log() {
+        let (cluster, outputs) = Cluster::builder().snapshot_interval(2).build_replicated(Counter(0));
+        let client = Client::new(0);
+        let timeout = Duration::from_secs(5);
+
+        let results: Vec<_> = ["a", "b", "c"]
+            .into_iter()
+            .map(|command| client.execute(command.to_string(), cluster.proposer(0), &outputs, timeout))
+            .collect();
+        let storage = cluster.storage();
+        cluster.shutdown();
+
+        assert_eq!(results, [Some((1, "a".to_string())), Some((2, "b".to_string())), Some((3, "c".to_string()))]);
+        let log = storage.lock().unwrap();
+        assert_eq!(log.snapshot().map(|snapshot| snapshot.index), Some(2));
+        assert_eq!(log.entries().len(), 1);
+        assert_eq!(log.len(), 3);
+    }
+
+    #[test]
+    fn test_reconfiguration_replaces_acceptors() {
+        let mut cluster: Cluster<Command> = Cluster::builder().alpha(2).build();
+        let client = Client::new(0);
+        let storage = cluster.storage();
+        let config = Configuration::new(vec![3, cluster.add_acceptor(), cluster.add_acceptor()], vec![4, cluster.add_learner()]);
+
+        client.consensus(None, Command::Apply("a".to_string()), cluster.proposer(0));
+        client.consensus(None, Command::Reconfigure(config.clone()), cluster.proposer(0));
+        client.consensus(None, Command::Apply("b".to_string()), cluster.proposer(0));
+        client.consensus(None, Command::Apply("c".to_string()), cluster.proposer(0));
+        wait_until(|| storage.lock().unwrap().len() == 4);
+        cluster.stop(1);
+        cluster.stop(2);
+        client.consensus(None, Command::Apply("d".to_string()), cluster.proposer(0));
+        wait_until(|| storage.lock().unwrap().len() == 5);
+        cluster.shutdown();
+
+        let log = storage.lock().unwrap();
+        assert_eq!(log.len(), 5);
+        assert_eq!(log.entries()[4], (4, Command::Apply("d".to_string())));
+        assert_eq!(log.membership().unwrap().config(4), &config);
+    }
+
+    #[test]
+    fn test_flexible_quorums_decide_with_one_acceptor() {
+        let client = Client::new(0);
+        let cluster = Cluster::builder().quorum_system(Flexible::new(3, 3, 1).unwrap()).build();
+        let storage = cluster.storage();
+
+        client.consensus(None, "a".to_string(), cluster.proposer(0));
+        wait_until(|| storage.lock().unwrap().len() == 1);
+        cluster.stop(1);
+        cluster.stop(2);
+        client.consensus(None, "b".to_string(), cluster.proposer(0));
+        wait_until(|| storage.lock().unwrap().len() == 2);
+        cluster.shutdown();
+
+        assert_eq!(storage.lock().unwrap().entries(), [(0, "a".to_string()), (1, "b".to_string())]);
+    }
+
+    #[test]
+    fn test_stopped_node_is_suspected() {
+        let heartbeats = HeartbeatConfig { interval: Duration::from_millis(10), timeout: Duration::from_millis(200) };
+        let cluster: Cluster = Cluster::builder().heartbeats(heartbeats).build();
+        let suspicions = cluster.suspicions();
+
+        assert!(suspicions.suspected_by(0).is_empty());
+        cluster.stop(2);
+        let suspected = wait_until(|| suspicions.suspected_by(0).contains(&2) && suspicions.suspected_by(4).contains(&2));
+        let live_suspected = suspicions.suspected_by(1).contains(&3);
+        cluster.shutdown();
+
+        assert!(suspected);
+        assert!(!live_suspected);
+    }
+}
@@ -0,0 +1,582 @@
+//! Binary wire format for `Message`.
+//!
+//! A frame is laid out as
+//!
+//! ```text
+//! | length: u32 | version: u8 | crc32: u32 | body |
+//! ```
+//!
+//! where `length` counts every byte after itself and the CRC-32 covers the
+//! body. The body is a tag byte followed by the variant's fields; integers are
+//! little-endian and values are length-prefixed `Value::encode` output, which
+//! for strings is their UTF-8. Decoding never panics: malformed input is
+//! reported as a `CodecError`.
+use std::fmt;
+use std::io::{self, Read};
+use crate::acceptor::SlotState;
+use crate::ballot::Ballot;
+use crate::membership::Configuration;
+use crate::message::Message;
+use crate::value::Value;
+
+/// Version byte written into every frame.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Largest frame accepted, so a corrupt length cannot trigger a huge allocation.
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+const HEADER_LEN: usize = 1 + 4;
+
+const CONSENSUS: u8 = 0;
+const PREPARE: u8 = 1;
+const PROMISE: u8 = 2;
+const PROPOSE: u8 = 3;
+const ACCEPT: u8 = 4;
+const DECIDED: u8 = 5;
+const NACK: u8 = 6;
+const TERMINATE: u8 = 7;
+const PREPARE_ALL: u8 = 8;
+const PROMISE_ALL: u8 = 9;
+const HEARTBEAT: u8 = 10;
+const ACCEPTED: u8 = 11;
+const CATCH_UP: u8 = 12;
+const CATCH_UP_REPLY: u8 = 13;
+const SNAPSHOT: u8 = 14;
+const COMPACT: u8 = 15;
+const CONFIGURE: u8 = 16;
+const INSPECT: u8 = 17;
+const INSPECT_REPLY: u8 = 18;
+const SUBMIT: u8 = 19;
+const COMMITTED: u8 = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodecError {
+    /// The input ended before the message was complete.
+    Truncated,
+    UnsupportedVersion(u8),
+    ChecksumMismatch,
+    FrameTooLarge(usize),
+    /// The length prefix is too small to hold the frame header.
+    FrameTooSmall(usize),
+    UnknownTag(u8),
+    InvalidUtf8,
+    /// The body decoded to a message but had bytes left over.
+    TrailingBytes,
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CodecError::Truncated => write!(f, "message is truncated"),
+            CodecError::UnsupportedVersion(version) => write!(f, "unsupported protocol version {}", version),
+            CodecError::ChecksumMismatch => write!(f, "checksum mismatch"),
+            CodecError::FrameTooLarge(len) => write!(f, "frame of {} bytes exceeds the limit", len),
+            CodecError::FrameTooSmall(len) => write!(f, "frame of {} bytes is shorter than its header", len),
+            CodecError::UnknownTag(tag) => write!(f, "unknown message tag {}", tag),
+            CodecError::InvalidUtf8 => write!(f, "string is not valid UTF-8"),
+            CodecError::TrailingBytes => write!(f, "trailing bytes after message"),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+impl From<CodecError> for io::Error {
+    fn from(error: CodecError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+}
+
+/// Encodes `message` as a complete frame. Fails if the frame would exceed
+/// `MAX_FRAME_LEN`, since no receiver would accept it.
+pub fn encode_frame<V: Value>(message: &Message<V>) -> Result<Vec<u8>, CodecError> {
+    let body = encode(message);
+    if HEADER_LEN + body.len() > MAX_FRAME_LEN {
+        return Err(CodecError::FrameTooLarge(HEADER_LEN + body.len()));
+    }
+    let mut frame = Vec::with_capacity(4 + HEADER_LEN + body.len());
+    frame.extend_from_slice(&((HEADER_LEN + body.len()) as u32).to_le_bytes());
+    frame.push(PROTOCOL_VERSION);
+    frame.extend_from_slice(&crc32(&body).to_le_bytes());
+    frame.extend_from_slice(&body);
+    Ok(frame)
+}
+
+/// Decodes the frame at the start of `bytes`, returning the message and the
+/// number of bytes consumed. `CodecError::Truncated` means more input is needed.
+pub fn decode_frame<V: Value>(bytes: &[u8]) -> Result<(Message<V>, usize), CodecError> {
+    let mut decoder = Decoder { bytes };
+    let len = frame_len(decoder.u32()?)?;
+    let message = decode_frame_body(decoder.take(len)?)?;
+    Ok((message, 4 + len))
+}
+
+/// Reads one frame from `reader`.
+pub fn read_frame<V: Value>(reader: &mut impl Read) -> io::Result<Message<V>> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let len = frame_len(u32::from_le_bytes(len))?;
+    let mut frame = vec![0; len];
+    reader.read_exact(&mut frame)?;
+    Ok(decode_frame_body(&frame)?)
+}
+
+fn frame_len(len: u32) -> Result<usize, CodecError> {
+    let len = len as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(CodecError::FrameTooLarge(len));
+    }
+    if len < HEADER_LEN {
+        return Err(CodecError::FrameTooSmall(len));
+    }
+    Ok(len)
+}
+
+/// Checks the version and checksum of a frame with its length prefix removed.
+fn decode_frame_body<V: Value>(frame: &[u8]) -> Result<Message<V>, CodecError> {
+    let mut decoder = Decoder { bytes: frame };
+    let version = decoder.u8()?;
+    if version != PROTOCOL_VERSION {
+        return Err(CodecError::UnsupportedVersion(version));
+    }
+    let checksum = decoder.u32()?;
+    if crc32(decoder.bytes) != checksum {
+        return Err(CodecError::ChecksumMismatch);
+    }
+    decode(decoder.bytes)
+}
+
+/// CRC-32 (IEEE 802.3), computed bitwise.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Binary encoding of a `Message` body: a tag byte followed by its fields.
+pub fn encode<V: Value>(message: &Message<V>) -> Vec<u8> {
+    let mut encoder = Encoder { bytes: vec![] };
+    match message {
+        Message::Consensus(id, value) => {
+            encoder.u8(CONSENSUS);
+            encoder.u64(*id);
+            encoder.value(value);
+        }
+        Message::Prepare(ballot, slot, value) => {
+            encoder.u8(PREPARE);
+            encoder.ballot(ballot);
+            encoder.u64(*slot);
+            encoder.value(value);
+        }
+        Message::Promise(ballot, slot, accepted_ballot, value, acceptor_id) => {
+            encoder.u8(PROMISE);
+            encoder.ballot(ballot);
+            encoder.u64(*slot);
+            encoder.optional_ballot(accepted_ballot);
+            encoder.value(value);
+            encoder.u64(*acceptor_id);
+        }
+        Message::PrepareAll(ballot, from) => {
+            encoder.u8(PREPARE_ALL);
+            encoder.ballot(ballot);
+            encoder.u64(*from);
+        }
+        Message::PromiseAll(ballot, from, accepted, acceptor_id) => {
+            encoder.u8(PROMISE_ALL);
+            encoder.ballot(ballot);
+            encoder.u64(*from);
+            encoder.u64(accepted.len() as u64);
+            for (slot, accepted_ballot, value) in accepted {
+                encoder.u64(*slot);
+                encoder.ballot(accepted_ballot);
+                encoder.value(value);
+            }
+            encoder.u64(*acceptor_id);
+        }
+        Message::Propose(ballot, slot, value) => {
+            encoder.u8(PROPOSE);
+            encoder.ballot(ballot);
+            encoder.u64(*slot);
+            encoder.value(value);
+        }
+        Message::Accept(ballot, slot, value, acceptor_id) => {
+            encoder.u8(ACCEPT);
+            encoder.ballot(ballot);
+            encoder.u64(*slot);
+            encoder.value(value);
+            encoder.u64(*acceptor_id);
+        }
+        Message::Accepted(ballot, slot, value, acceptor_id) => {
+            encoder.u8(ACCEPTED);
+            encoder.ballot(ballot);
+            encoder.u64(*slot);
+            encoder.value(value);
+            encoder.u64(*acceptor_id);
+        }
+        Message::Decided(slot) => {
+            encoder.u8(DECIDED);
+            encoder.u64(*slot);
+        }
+        Message::Nack(ballot, slot, max_id, acceptor_id) => {
+            encoder.u8(NACK);
+            encoder.ballot(ballot);
+            encoder.u64(*slot);
+            encoder.ballot(max_id);
+            encoder.u64(*acceptor_id);
+        }
+        Message::Heartbeat(from, leader) => {
+            encoder.u8(HEARTBEAT);
+            encoder.u64(*from);
+            encoder.optional_ballot(leader);
+        }
+        Message::CatchUp(from, start, end) => {
+            encoder.u8(CATCH_UP);
+            encoder.u64(*from);
+            encoder.u64(*start);
+            encoder.u64(*end);
+        }
+        Message::CatchUpReply(entries) => {
+            encoder.u8(CATCH_UP_REPLY);
+            encoder.u64(entries.len() as u64);
+            for (slot, value) in entries {
+                encoder.u64(*slot);
+                encoder.value(value);
+            }
+        }
+        Message::Snapshot(index, offset, len, part) => {
+            encoder.u8(SNAPSHOT);
+            encoder.u64(*index);
+            encoder.u64(*offset);
+            encoder.u64(*len);
+            encoder.value(part);
+        }
+        Message::Compact(index) => {
+            encoder.u8(COMPACT);
+            encoder.u64(*index);
+        }
+        Message::Configure(known, changes) => {
+            encoder.u8(CONFIGURE);
+            encoder.u64(*known);
+            encoder.u64(changes.len() as u64);
+            for (slot, config) in changes {
+                encoder.u64(*slot);
+                encoder.configuration(config);
+            }
+        }
+        Message::Inspect(from) => {
+            encoder.u8(INSPECT);
+            encoder.u64(*from);
+        }
+        Message::InspectReply(acceptor_id, promised, truncated, slots) => {
+            encoder.u8(INSPECT_REPLY);
+            encoder.u64(*acceptor_id);
+            encoder.ballot(promised);
+            encoder.u64(*truncated);
+            encoder.u64(slots.len() as u64);
+            for (slot, state) in slots {
+                encoder.u64(*slot);
+                encoder.ballot(&state.max_id);
+                encoder.optional_ballot(&state.accepted.as_ref().map(|(ballot, _)| *ballot));
+                if let Some((_, value)) = &state.accepted {
+                    encoder.value(value);
+                }
+            }
+        }
+        Message::Submit(from, id, value) => {
+            encoder.u8(SUBMIT);
+            encoder.u64(*from);
+            encoder.u64(*id);
+            encoder.value(value);
+        }
+        Message::Committed(id, slot) => {
+            encoder.u8(COMMITTED);
+            encoder.u64(*id);
+            encoder.u64(*slot);
+        }
+        Message::Terminate => encoder.u8(TERMINATE),
+    }
+    encoder.bytes
+}
+
+/// Inverse of `encode`. Rejects anything `encode` cannot produce.
+pub fn decode<V: Value>(bytes: &[u8]) -> Result<Message<V>, CodecError> {
+    let mut decoder = Decoder { bytes };
+    let message = match decoder.u8()? {
+        CONSENSUS => Message::Consensus(decoder.u64()?, decoder.value()?),
+        PREPARE => Message::Prepare(decoder.ballot()?, decoder.u64()?, decoder.value()?),
+        PROMISE => Message::Promise(decoder.ballot()?, decoder.u64()?, decoder.optional_ballot()?, decoder.value()?, decoder.u64()?),
+        PREPARE_ALL => Message::PrepareAll(decoder.ballot()?, decoder.u64()?),
+        PROMISE_ALL => {
+            let ballot = decoder.ballot()?;
+            let from = decoder.u64()?;
+            let mut accepted = vec![];
+            for _ in 0..decoder.u64()? {
+                accepted.push((decoder.u64()?, decoder.ballot()?, decoder.value()?));
+            }
+            Message::PromiseAll(ballot, from, accepted, decoder.u64()?)
+        }
+        PROPOSE => Message::Propose(decoder.ballot()?, decoder.u64()?, decoder.value()?),
+        ACCEPT => Message::Accept(decoder.ballot()?, decoder.u64()?, decoder.value()?, decoder.u64()?),
+        ACCEPTED => Message::Accepted(decoder.ballot()?, decoder.u64()?, decoder.value()?, decoder.u64()?),
+        DECIDED => Message::Decided(decoder.u64()?),
+        NACK => Message::Nack(decoder.ballot()?, decoder.u64()?, decoder.ballot()?, decoder.u64()?),
+        HEARTBEAT => Message::Heartbeat(decoder.u64()?, decoder.optional_ballot()?),
+        CATCH_UP => Message::CatchUp(decoder.u64()?, decoder.u64()?, decoder.u64()?),
+        CATCH_UP_REPLY => {
+            let mut entries = vec![];
+            for _ in 0..decoder.u64()? {
+                entries.push((decoder.u64()?, decoder.value()?));
+            }
+            Message::CatchUpReply(entries)
+        }
+        SNAPSHOT => Message::Snapshot(decoder.u64()?, decoder.u64()?, decoder.u64()?, decoder.value()?),
+        COMPACT => Message::Compact(decoder.u64()?),
+        CONFIGURE => {
+            let known = decoder.u64()?;
+            let mut changes = vec![];
+            for _ in 0..decoder.u64()? {
+                changes.push((decoder.u64()?, decoder.configuration()?));
+            }
+            Message::Configure(known, changes)
+        }
+        INSPECT => Message::Inspect(decoder.u64()?),
+        INSPECT_REPLY => {
+            let acceptor_id = decoder.u64()?;
+            let promised = decoder.ballot()?;
+            let truncated = decoder.u64()?;
+            let mut slots = vec![];
+            for _ in 0..decoder.u64()? {
+                let slot = decoder.u64()?;
+                let max_id = decoder.ballot()?;
+                let accepted = match decoder.optional_ballot()? {
+                    Some(ballot) => Some((ballot, decoder.value()?)),
+                    None => None,
+                };
+                slots.push((slot, SlotState { max_id, accepted }));
+            }
+            Message::InspectReply(acceptor_id, promised, truncated, slots)
+        }
+        SUBMIT => Message::Submit(decoder.u64()?, decoder.u64()?, decoder.value()?),
+        COMMITTED => Message::Committed(decoder.u64()?, decoder.u64()?),
+        TERMINATE => Message::Terminate,
+        tag => return Err(CodecError::UnknownTag(tag)),
+    };
+    if !decoder.bytes.is_empty() {
+        return Err(CodecError::TrailingBytes);
+    }
+    Ok(message)
+}
+
+pub(crate) struct Encoder {
+    pub(crate) bytes: Vec<u8>,
+}
+
+impl Encoder {
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn ballot(&mut self, ballot: &Ballot) {
+        self.u64(ballot.round);
+        self.u64(ballot.proposer_id);
+    }
+
+    fn optional_ballot(&mut self, ballot: &Option<Ballot>) {
+        match ballot {
+            Some(ballot) => {
+                self.u8(1);
+                self.ballot(ballot);
+            }
+            None => self.u8(0),
+        }
+    }
+
+    pub(crate) fn value<V: Value>(&mut self, value: &V) {
+        let mut bytes = vec![];
+        value.encode(&mut bytes);
+        self.u64(bytes.len() as u64);
+        self.bytes.extend_from_slice(&bytes);
+    }
+
+    fn ids(&mut self, ids: &[u64]) {
+        self.u64(ids.len() as u64);
+        for id in ids {
+            self.u64(*id);
+        }
+    }
+
+    pub(crate) fn configuration(&mut self, config: &Configuration) {
+        self.ids(&config.acceptors);
+        self.ids(&config.learners);
+    }
+}
+
+pub(crate) struct Decoder<'a> {
+    pub(crate) bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], CodecError> {
+        if self.bytes.len() < len {
+            return Err(CodecError::Truncated);
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, CodecError> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, CodecError> {
+        self.take(4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, CodecError> {
+        self.take(8).map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn ballot(&mut self) -> Result<Ballot, CodecError> {
+        Ok(Ballot::new(self.u64()?, self.u64()?))
+    }
+
+    fn optional_ballot(&mut self) -> Result<Option<Ballot>, CodecError> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(self.ballot()?)),
+            flag => Err(CodecError::UnknownTag(flag)),
+        }
+    }
+
+    pub(crate) fn value<V: Value>(&mut self) -> Result<V, CodecError> {
+        let len = usize::try_from(self.u64()?).map_err(|_| CodecError::Truncated)?;
+        V::decode(self.take(len)?)
+    }
+
+    fn ids(&mut self) -> Result<Vec<u64>, CodecError> {
+        (0..self.u64()?).map(|_| self.u64()).collect()
+    }
+
+    pub(crate) fn configuration(&mut self) -> Result<Configuration, CodecError> {
+        Ok(Configuration::new(self.ids()?, self.ids()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    fn messages() -> Vec<Message> {
+        let ballot = Ballot::new(3, 1);
+        vec![
+            Message::Consensus(7, "value".to_string()),
+            Message::Prepare(ballot, 2, "∑".to_string()),
+            Message::Promise(ballot, 2, Some(Ballot::new(2, 0)), "a".to_string(), 1),
+            Message::Promise(ballot, 2, None, String::new(), 3),
+            Message::PrepareAll(ballot, 4),
+            Message::PromiseAll(ballot, 4, vec![(4, Ballot::new(2, 0), "a".to_string()), (6, ballot, String::new())], 2),
+            Message::PromiseAll(ballot, 0, vec![], 1),
+            Message::Propose(ballot, u64::MAX, "b".to_string()),
+            Message::Accept(ballot, 0, "c".to_string(), 2),
+            Message::Accepted(ballot, 0, "c".to_string(), 3),
+            Message::Decided(9),
+            Message::Nack(ballot, 4, Ballot::new(5, 2), 3),
+            Message::Heartbeat(4, Some(ballot)),
+            Message::Heartbeat(4, None),
+            Message::CatchUp(6, 2, u64::MAX),
+            Message::CatchUpReply(vec![(2, "a".to_string()), (3, String::new())]),
+            Message::CatchUpReply(vec![]),
+            Message::Snapshot(7, 4, 6, vec![0, 0xff]),
+            Message::Compact(7),
+            Message::Configure(3, vec![(0, Configuration::new(vec![1, 2, 3], vec![4])), (5, Configuration::new(vec![], vec![]))]),
+            Message::Inspect(8),
+            Message::InspectReply(
+                2,
+                ballot,
+                1,
+                vec![
+                    (1, SlotState { max_id: ballot, accepted: Some((Ballot::new(2, 0), "a".to_string())) }),
+                    (3, SlotState { max_id: ballot, accepted: None }),
+                ],
+            ),
+            Message::InspectReply(1, Ballot::default(), 0, vec![]),
+            Message::Submit(9, 4, "d".to_string()),
+            Message::Committed(4, 11),
+            Message::Terminate,
+        ]
+    }
+
+    #[test]
+    fn test_round_trip_every_variant() {
+        for message in messages() {
+            let frame = encode_frame(&message).unwrap();
+            let (decoded, len) = decode_frame(&frame).unwrap();
+            assert_eq!(len, frame.len());
+            assert_eq!(decoded, message);
+            assert_eq!(read_frame(&mut &frame[..]).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn test_binary_values_round_trip() {
+        let message = Message::Accept(Ballot::new(1, 0), 0, vec![0, 0xff, 0xc3], 1);
+        assert_eq!(decode_frame(&encode_frame(&message).unwrap()).unwrap(), (message.clone(), encode_frame(&message).unwrap().len()));
+        // Not UTF-8, so the same bytes are no `String`.
+        assert_eq!(decode::<String>(&encode(&message)).unwrap_err(), CodecError::InvalidUtf8);
+    }
+
+    #[test]
+    fn test_truncated_frames_are_errors() {
+        for message in messages() {
+            let frame = encode_frame(&message).unwrap();
+            for len in 0..frame.len() {
+                assert_eq!(decode_frame::<String>(&frame[..len]).unwrap_err(), CodecError::Truncated);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rejects_corrupt_frames() {
+        let mut frame = encode_frame(&Message::<String>::Decided(1)).unwrap();
+        frame[4] = PROTOCOL_VERSION + 1;
+        assert_eq!(decode_frame::<String>(&frame).unwrap_err(), CodecError::UnsupportedVersion(PROTOCOL_VERSION + 1));
+
+        let mut frame = encode_frame(&Message::<String>::Decided(1)).unwrap();
+        *frame.last_mut().unwrap() ^= 1;
+        assert_eq!(decode_frame::<String>(&frame).unwrap_err(), CodecError::ChecksumMismatch);
+
+        let frame = (MAX_FRAME_LEN as u32 + 1).to_le_bytes();
+        assert_eq!(decode_frame::<String>(&frame).unwrap_err(), CodecError::FrameTooLarge(MAX_FRAME_LEN + 1));
+
+        assert_eq!(decode_frame::<String>(&[1, 0, 0, 0]).unwrap_err(), CodecError::FrameTooSmall(1));
+        let message = Message::<String>::Snapshot(1, 0, MAX_FRAME_LEN as u64, vec![0; MAX_FRAME_LEN]);
+        assert!(matches!(encode_frame(&message), Err(CodecError::FrameTooLarge(_))));
+        assert_eq!(decode::<String>(&[200]).unwrap_err(), CodecError::UnknownTag(200));
+        assert_eq!(decode::<String>(&[DECIDED, 0, 0, 0, 0, 0, 0, 0, 0, 0]).unwrap_err(), CodecError::TrailingBytes);
+    }
+
+    #[test]
+    fn test_random_input_never_panics() {
+        let mut rng = Rng::new(42);
+        for _ in 0..10_000 {
+            let len = rng.below(64) as usize;
+            let mut bytes: Vec<u8> = (0..len).map(|_| rng.next_u64() as u8).collect();
+            if !bytes.is_empty() {
+                bytes[0] %= 21;
+            }
+            let _ = decode::<String>(&bytes);
+            let _ = decode_frame::<Vec<u8>>(&bytes);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! Heartbeats and timeout-based failure detection.
+//!
+//! Every node that owns a `FailureDetector` sends `Message::Heartbeat` to its
+//! peers at a fixed interval and suspects any peer it has not heard from
+//! within the timeout. Suspicion is only a hint: a slow node is
+//! indistinguishable from a dead one, so nothing that affects safety may
+//! depend on it.
+use std::collections::{BTreeMap, BTreeSet};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::ballot::Ballot;
+use crate::formatting::log;
+use crate::message::Message;
+use crate::transport::{NodeId, Transport};
+use crate::value::Value;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HeartbeatConfig {
+    /// Time between heartbeats sent to each peer.
+    pub interval: Duration,
+    /// Silence after which a peer is suspected.
+    pub timeout: Duration,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        HeartbeatConfig {
+            interval: Duration::from_millis(100),
+            timeout: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Which peers each node currently suspects, shared with whoever wants to
+/// watch the cluster. Detectors update it every time they send heartbeats.
+#[derive(Debug, Clone, Default)]
+pub struct Suspicions(Arc<Mutex<BTreeMap<NodeId, BTreeSet<NodeId>>>>);
+
+impl Suspicions {
+    pub fn new() -> Self {
+        Suspicions::default()
+    }
+
+    /// Peers suspected by node `id`.
+    pub fn suspected_by(&self, id: NodeId) -> BTreeSet<NodeId> {
+        self.0.lock().unwrap().get(&id).cloned().unwrap_or_default()
+    }
+
+    /// Suspected peers of every reporting node.
+    pub fn all(&self) -> BTreeMap<NodeId, BTreeSet<NodeId>> {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn report(&self, id: NodeId, suspected: BTreeSet<NodeId>) {
+        self.0.lock().unwrap().insert(id, suspected);
+    }
+}
+
+// The report is an outlet rather than part of the detector's state, so it is
+// ignored when detectors are compared or hashed.
+impl PartialEq for Suspicions {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Suspicions {}
+
+impl Hash for Suspicions {
+    fn hash<H: Hasher>(&self, _state: &mut H) {}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FailureDetector {
+    id: NodeId,
+    peers: Vec<NodeId>,
+    config: HeartbeatConfig,
+    last_heard: BTreeMap<NodeId, Instant>,
+    next_heartbeat: Instant,
+    report: Option<Suspicions>,
+}
+
+impl FailureDetector {
+    /// Watches `peers` on behalf of node `id`. Every peer gets a full timeout
+    /// from `now` before it can be suspected.
+    pub fn new(id: NodeId, peers: Vec<NodeId>, config: HeartbeatConfig, now: Instant) -> Self {
+        let peers: Vec<NodeId> = peers.into_iter().filter(|peer| *peer != id).collect();
+        FailureDetector {
+            id,
+            last_heard: peers.iter().map(|peer| (*peer, now)).collect(),
+            peers,
+            config,
+            next_heartbeat: now,
+            report: None,
+        }
+    }
+
+    /// Publishes this node's suspicions to `report`.
+    pub fn with_report(mut self, report: Suspicions) -> Self {
+        self.report = Some(report);
+        self
+    }
+
+    /// Records a sign of life from `id`.
+    pub fn heard_from(&mut self, id: NodeId, now: Instant) {
+        if let Some(last) = self.last_heard.get_mut(&id) {
+            *last = (*last).max(now);
+        }
+    }
+
+    /// Whether `id` has been silent for longer than the timeout. Nodes that
+    /// are not watched are never suspected.
+    pub fn is_suspected(&self, id: NodeId, now: Instant) -> bool {
+        self.last_heard.get(&id).is_some_and(|last| now.saturating_duration_since(*last) > self.config.timeout)
+    }
+
+    pub fn suspected(&self, now: Instant) -> BTreeSet<NodeId> {
+        self.peers.iter().copied().filter(|peer| self.is_suspected(*peer, now)).collect()
+    }
+
+    /// When the next heartbeat is due.
+    pub fn next_deadline(&self) -> Instant {
+        self.next_heartbeat
+    }
+
+    /// Sends a heartbeat to every peer if one is due. `leader` is the ballot
+    /// the sender leads with, if any.
+    pub fn tick<V: Value>(&mut self, leader: Option<Ballot>, transport: &dyn Transport<V>, now: Instant) {
+        if now < self.next_heartbeat {
+            return;
+        }
+        self.next_heartbeat = now + self.config.interval;
+        transport.broadcast(&self.peers, &Message::Heartbeat(self.id, leader));
+        let suspected = self.suspected(now);
+        if !suspected.is_empty() {
+            log!("[Node {}] Suspects {:?}", self.id, suspected);
+        }
+        if let Some(report) = &self.report {
+            report.report(self.id, suspected);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::tests::drain;
+    use crate::transport::ChannelTransport;
+
+    #[test]
+    fn test_silent_peers_are_suspected() {
+        let nodes: Vec<ChannelTransport> = ChannelTransport::network(&[0, 1, 2]);
+        let start = Instant::now();
+        let config = HeartbeatConfig { interval: Duration::from_millis(10), timeout: Duration::from_millis(50) };
+        let report = Suspicions::new();
+        let mut detector = FailureDetector::new(0, vec![0, 1, 2], config, start).with_report(report.clone());
+
+        detector.tick(None, &nodes[0], start);
+        detector.tick(None, &nodes[0], start + Duration::from_millis(5));
+        assert_eq!(drain(&nodes[1]), vec![Message::Heartbeat(0, None)]);
+        assert_eq!(detector.next_deadline(), start + Duration::from_millis(10));
+
+        detector.heard_from(1, start + Duration::from_millis(40));
+        let later = start + Duration::from_millis(60);
+        assert!(!detector.is_suspected(1, later));
+        assert!(detector.is_suspected(2, later));
+        assert!(!detector.is_suspected(7, later));
+
+        detector.tick(None, &nodes[0], later);
+        assert_eq!(report.suspected_by(0), BTreeSet::from([2]));
+        assert!(drain(&nodes[0]).is_empty());
+    }
+}
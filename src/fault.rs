@@ -0,0 +1,266 @@
+//! Network fault injection.
+//!
+//! `FaultConfig` describes what can go wrong on each link: drops, duplicates,
+//! delays and reordering, plus partitions that cut links entirely. The
+//! simulator applies it to every message it schedules, and `FaultyTransport`
+//! applies it around any real `Transport`.
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::message::Message;
+use crate::rng::Rng;
+use crate::transport::{NodeId, Transport};
+use crate::value::Value;
+
+/// Faults applied to messages on one directed link.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LinkFaults {
+    /// Probability that a message is lost.
+    pub drop: f64,
+    /// Probability that a message is delivered twice.
+    pub duplicate: f64,
+    /// Probability that a message is held back so later messages overtake it.
+    pub reorder: f64,
+    /// Extra delay added to every message, drawn uniformly from `min_delay..=max_delay`.
+    pub min_delay: Duration,
+    pub max_delay: Duration,
+}
+
+/// Held-back messages wait this long on top of their normal delay.
+const REORDER_HOLD: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Clone, Default)]
+pub struct FaultConfig {
+    default: LinkFaults,
+    links: HashMap<(NodeId, NodeId), LinkFaults>,
+    blocked: HashSet<(NodeId, NodeId)>,
+}
+
+impl FaultConfig {
+    /// Applies `default` to every link without its own settings.
+    pub fn new(default: LinkFaults) -> Self {
+        FaultConfig { default, ..FaultConfig::default() }
+    }
+
+    /// Overrides the faults on the link from `from` to `to`.
+    pub fn link(mut self, from: NodeId, to: NodeId, faults: LinkFaults) -> Self {
+        self.links.insert((from, to), faults);
+        self
+    }
+
+    /// Cuts every link between a node in `a` and a node in `b`, both ways.
+    pub fn partition(&mut self, a: &[NodeId], b: &[NodeId]) {
+        for x in a {
+            for y in b {
+                self.blocked.insert((*x, *y));
+                self.blocked.insert((*y, *x));
+            }
+        }
+    }
+
+    /// Removes every partition.
+    pub fn heal(&mut self) {
+        self.blocked.clear();
+    }
+
+    /// Decides the fate of one message: one extra delay per copy to deliver,
+    /// or nothing if it is lost.
+    pub fn plan(&self, from: NodeId, to: NodeId, rng: &mut Rng) -> Vec<Duration> {
+        if self.blocked.contains(&(from, to)) {
+            return vec![];
+        }
+        let faults = self.links.get(&(from, to)).unwrap_or(&self.default);
+        if rng.next_f64() < faults.drop {
+            return vec![];
+        }
+        let copies = if rng.next_f64() < faults.duplicate { 2 } else { 1 };
+        (0..copies)
+            .map(|_| {
+                let spread = faults.max_delay.saturating_sub(faults.min_delay).as_micros() as u64;
+                let mut delay = faults.min_delay + Duration::from_micros(rng.below(spread + 1));
+                if rng.next_f64() < faults.reorder {
+                    delay += REORDER_HOLD.max(faults.max_delay);
+                }
+                delay
+            })
+            .collect()
+    }
+}
+
+/// A `FaultConfig` shared by every node, so partitions can be changed while
+/// the cluster runs.
+#[derive(Clone)]
+pub struct FaultHandle {
+    config: Arc<Mutex<FaultConfig>>,
+}
+
+impl FaultHandle {
+    pub fn new(config: FaultConfig) -> Self {
+        FaultHandle { config: Arc::new(Mutex::new(config)) }
+    }
+
+    pub fn partition(&self, a: &[NodeId], b: &[NodeId]) {
+        self.config.lock().unwrap().partition(a, b);
+    }
+
+    pub fn heal(&self) {
+        self.config.lock().unwrap().heal();
+    }
+
+    fn plan(&self, from: NodeId, to: NodeId, rng: &mut Rng) -> Vec<Duration> {
+        self.config.lock().unwrap().plan(from, to, rng)
+    }
+}
+
+struct Delayed<V> {
+    at: Instant,
+    sequence: u64,
+    to: NodeId,
+    message: Message<V>,
+}
+
+impl<V> PartialEq for Delayed<V> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.sequence) == (other.at, other.sequence)
+    }
+}
+
+impl<V> Eq for Delayed<V> {}
+
+impl<V> PartialOrd for Delayed<V> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<V> Ord for Delayed<V> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.at, self.sequence).cmp(&(other.at, other.sequence))
+    }
+}
+
+/// Wraps a transport and applies a `FaultConfig` to everything it sends.
+///
+/// Delayed messages are held by the sender and released from its own
+/// `send` and `recv_deadline` calls, so no extra threads are needed.
+pub struct FaultyTransport<T, V = String> {
+    inner: T,
+    faults: FaultHandle,
+    rng: Mutex<Rng>,
+    pending: Mutex<BinaryHeap<Reverse<Delayed<V>>>>,
+    sequence: Mutex<u64>,
+}
+
+impl<V: Value, T: Transport<V>> FaultyTransport<T, V> {
+    pub fn new(inner: T, faults: FaultHandle, seed: u64) -> Self {
+        FaultyTransport {
+            inner,
+            faults,
+            rng: Mutex::new(Rng::new(seed)),
+            pending: Mutex::new(BinaryHeap::new()),
+            sequence: Mutex::new(0),
+        }
+    }
+
+    /// Sends every held-back message whose delay has passed.
+    fn flush(&self, now: Instant) {
+        loop {
+            let due = {
+                let mut pending = self.pending.lock().unwrap();
+                match pending.peek() {
+                    Some(Reverse(delayed)) if delayed.at <= now => pending.pop().unwrap().0,
+                    _ => return,
+                }
+            };
+            if let Err(e) = self.inner.send(due.to, due.message) {
+                println!("[Transport] Failed to send delayed message to {}: {}", due.to, e);
+            }
+        }
+    }
+
+    fn next_release(&self) -> Option<Instant> {
+        self.pending.lock().unwrap().peek().map(|Reverse(delayed)| delayed.at)
+    }
+}
+
+impl<V: Value, T: Transport<V>> Transport<V> for FaultyTransport<T, V> {
+    fn id(&self) -> NodeId {
+        self.inner.id()
+    }
+
+    fn send(&self, to: NodeId, message: Message<V>) -> io::Result<()> {
+        let now = Instant::now();
+        let delays = self.faults.plan(self.id(), to, &mut self.rng.lock().unwrap());
+        for delay in delays {
+            if delay.is_zero() {
+                self.inner.send(to, message.clone())?;
+            } else {
+                let mut sequence = self.sequence.lock().unwrap();
+                *sequence += 1;
+                self.pending.lock().unwrap().push(Reverse(Delayed { at: now + delay, sequence: *sequence, to, message: message.clone() }));
+            }
+        }
+        self.flush(now);
+        Ok(())
+    }
+
+    fn recv_deadline(&self, deadline: Option<Instant>) -> io::Result<Option<Message<V>>> {
+        loop {
+            self.flush(Instant::now());
+            let wake = match (deadline, self.next_release()) {
+                (Some(deadline), Some(release)) => Some(deadline.min(release)),
+                (deadline, release) => deadline.or(release),
+            };
+            if let Some(message) = self.inner.recv_deadline(wake)? {
+                return Ok(Some(message));
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::tests::drain;
+    use crate::transport::ChannelTransport;
+
+    #[test]
+    fn test_plan_applies_link_settings() {
+        let mut rng = Rng::new(1);
+        let lossy = LinkFaults { drop: 1.0, ..LinkFaults::default() };
+        let doubled = LinkFaults { duplicate: 1.0, ..LinkFaults::default() };
+        let mut config = FaultConfig::new(doubled).link(0, 1, lossy);
+
+        assert!(config.plan(0, 1, &mut rng).is_empty());
+        assert_eq!(config.plan(1, 0, &mut rng).len(), 2);
+        config.partition(&[1], &[2]);
+        assert!(config.plan(2, 1, &mut rng).is_empty());
+        config.heal();
+        assert_eq!(config.plan(2, 1, &mut rng).len(), 2);
+    }
+
+    #[test]
+    fn test_faulty_transport_delays_and_partitions() {
+        let delay = Duration::from_millis(30);
+        let faults = FaultHandle::new(FaultConfig::new(LinkFaults { min_delay: delay, max_delay: delay, ..LinkFaults::default() }));
+        let mut nodes = ChannelTransport::<String>::network(&[0, 1]).into_iter();
+        let a = FaultyTransport::new(nodes.next().unwrap(), faults.clone(), 0);
+        let b = FaultyTransport::new(nodes.next().unwrap(), faults.clone(), 1);
+
+        let sent = Instant::now();
+        a.send(1, Message::Decided(1)).unwrap();
+        assert!(drain(&b).is_empty());
+        // The delayed message is released while `a` waits for its own messages.
+        assert_eq!(a.recv_deadline(Some(sent + delay * 2)).unwrap(), None);
+        assert_eq!(drain(&b), vec![Message::Decided(1)]);
+
+        faults.partition(&[0], &[1]);
+        b.send(0, Message::Decided(2)).unwrap();
+        assert_eq!(a.recv_deadline(Some(Instant::now() + delay * 2)).unwrap(), None);
+    }
+}
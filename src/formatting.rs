@@ -1,6 +1,39 @@
+use std::cell::Cell;
+
+thread_local! {
+    static MUTED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// `println!` that stays silent while logging is muted on this thread.
+macro_rules! log {
+    ($($arg:tt)*) => {
+        if !$crate::formatting::is_muted() {
+            println!($($arg)*);
+        }
+    };
+}
+pub(crate) use log;
+
+pub(crate) fn is_muted() -> bool {
+    MUTED.with(Cell::get)
+}
+
+/// Runs `f` with node logging muted on this thread, for callers that drive
+/// far more messages than anyone could read.
+pub(crate) fn muted<T>(f: impl FnOnce() -> T) -> T {
+    let previous = MUTED.with(|muted| muted.replace(true));
+    let result = f();
+    MUTED.with(|muted| muted.set(previous));
+    result
+}
+
 pub fn print_green(text: &str) {
-    println!("\x1b[32m{}\x1b[0m", text);
+    if !is_muted() {
+        println!("\x1b[32m{}\x1b[0m", text);
+    }
 }
 pub fn print_red(text: &str) {
-    println!("\x1b[31m{}\x1b[0m", text);
+    if !is_muted() {
+        println!("\x1b[31m{}\x1b[0m", text);
+    }
 }
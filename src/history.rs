@@ -0,0 +1,140 @@
+//! Records when client operations start and finish, for checking with
+//! `linearizability::check`.
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::learner::Storage;
+use crate::value::Value;
+
+/// One client operation. `output` and `complete` stay `None` while the
+/// operation is pending; a pending operation may or may not have taken effect.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operation<I, O> {
+    pub client: u64,
+    pub input: I,
+    pub output: Option<O>,
+    pub invoke: Duration,
+    pub complete: Option<Duration>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct History<I, O> {
+    operations: Vec<Operation<I, O>>,
+}
+
+impl<I, O> Default for History<I, O> {
+    fn default() -> Self {
+        History { operations: vec![] }
+    }
+}
+
+impl<I, O> History<I, O> {
+    pub fn new() -> Self {
+        History::default()
+    }
+
+    /// Records that `client` started an operation at `at`. Returns its index.
+    pub fn invoke(&mut self, client: u64, input: I, at: Duration) -> usize {
+        self.operations.push(Operation { client, input, output: None, invoke: at, complete: None });
+        self.operations.len() - 1
+    }
+
+    /// Records that operation `index` returned `output` at `at`.
+    pub fn complete(&mut self, index: usize, output: O, at: Duration) {
+        let operation = &mut self.operations[index];
+        operation.output = Some(output);
+        operation.complete = Some(at);
+    }
+
+    pub fn operations(&self) -> &[Operation<I, O>] {
+        &self.operations
+    }
+}
+
+impl<V: PartialEq> History<V, u64> {
+    /// Completes the oldest pending append of `value` with the slot it was
+    /// decided in. Returns `false` if no client is waiting for `value`.
+    pub fn complete_append(&mut self, slot: u64, value: &V, at: Duration) -> bool {
+        let pending = self.operations.iter().position(|op| op.complete.is_none() && op.input == *value);
+        match pending {
+            Some(index) => {
+                self.complete(index, slot, at);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+struct RecorderState<V> {
+    history: History<V, u64>,
+    observed: u64,
+}
+
+/// Thread-safe recorder of appends to the decided log, timed by the wall
+/// clock. Clients record invocations and a watcher feeds it the learner's
+/// `Storage` to complete them.
+pub struct Recorder<V = String> {
+    start: Instant,
+    state: Arc<Mutex<RecorderState<V>>>,
+}
+
+impl<V> Clone for Recorder<V> {
+    fn clone(&self) -> Self {
+        Recorder { start: self.start, state: self.state.clone() }
+    }
+}
+
+impl<V> Default for Recorder<V> {
+    fn default() -> Self {
+        Recorder { start: Instant::now(), state: Arc::new(Mutex::new(RecorderState { history: History::new(), observed: 0 })) }
+    }
+}
+
+impl<V: Value> Recorder<V> {
+    pub fn new() -> Self {
+        Recorder::default()
+    }
+
+    pub fn invoke(&self, client: u64, value: V) {
+        let at = self.start.elapsed();
+        self.state.lock().unwrap().history.invoke(client, value, at);
+    }
+
+    /// Completes pending appends whose values have been decided since the
+    /// last call.
+    pub fn observe(&self, storage: &Storage<V>) {
+        let at = self.start.elapsed();
+        let log = storage.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+        for (slot, value) in log.range(state.observed, u64::MAX) {
+            state.history.complete_append(*slot, value, at);
+        }
+        state.observed = log.len();
+    }
+
+    pub fn history(&self) -> History<V, u64> {
+        self.state.lock().unwrap().history.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recorder_completes_appends_in_invoke_order() {
+        let recorder = Recorder::new();
+        let storage = Storage::default();
+        recorder.invoke(0, "a".to_string());
+        recorder.invoke(1, "a".to_string());
+        recorder.invoke(1, "b".to_string());
+
+        storage.lock().unwrap().push("b".to_string());
+        storage.lock().unwrap().push("a".to_string());
+        recorder.observe(&storage);
+        recorder.observe(&storage);
+
+        let outputs: Vec<_> = recorder.history().operations().iter().map(|op| op.output).collect();
+        assert_eq!(outputs, vec![Some(1), None, Some(0)]);
+    }
+}
@@ -0,0 +1,290 @@
+//! A replicated key-value store built on the consensus core.
+//!
+//! Every command, reads included, goes through the log, so each `KvClient`
+//! call observes every command decided before it.
+use crossbeam_channel::Sender;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+use crate::client::Client;
+use crate::codec::{CodecError, Decoder, Encoder};
+use crate::message::Message;
+use crate::state_machine::{Outputs, Request, StateMachine};
+use crate::value::Value;
+
+const GET: u8 = 0;
+const PUT: u8 = 1;
+const DELETE: u8 = 2;
+const COMPARE_AND_SWAP: u8 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum KvCommand {
+    Get(String),
+    Put(String, String),
+    Delete(String),
+    /// Sets `key` to `new` if it currently holds `expected`. `None` stands
+    /// for a missing key on either side.
+    CompareAndSwap { key: String, expected: Option<String>, new: Option<String> },
+}
+
+impl Value for KvCommand {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        let mut encoder = Encoder { bytes: vec![] };
+        match self {
+            KvCommand::Get(key) => {
+                encoder.u8(GET);
+                encoder.value(key);
+            }
+            KvCommand::Put(key, value) => {
+                encoder.u8(PUT);
+                encoder.value(key);
+                encoder.value(value);
+            }
+            KvCommand::Delete(key) => {
+                encoder.u8(DELETE);
+                encoder.value(key);
+            }
+            KvCommand::CompareAndSwap { key, expected, new } => {
+                encoder.u8(COMPARE_AND_SWAP);
+                encoder.value(key);
+                encode_optional(&mut encoder, expected);
+                encode_optional(&mut encoder, new);
+            }
+        }
+        bytes.extend_from_slice(&encoder.bytes);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, CodecError> {
+        let mut decoder = Decoder { bytes };
+        let command = match decoder.u8()? {
+            GET => KvCommand::Get(decoder.value()?),
+            PUT => KvCommand::Put(decoder.value()?, decoder.value()?),
+            DELETE => KvCommand::Delete(decoder.value()?),
+            COMPARE_AND_SWAP => KvCommand::CompareAndSwap {
+                key: decoder.value()?,
+                expected: decode_optional(&mut decoder)?,
+                new: decode_optional(&mut decoder)?,
+            },
+            tag => return Err(CodecError::UnknownTag(tag)),
+        };
+        if !decoder.bytes.is_empty() {
+            return Err(CodecError::TrailingBytes);
+        }
+        Ok(command)
+    }
+}
+
+fn encode_optional(encoder: &mut Encoder, value: &Option<String>) {
+    match value {
+        Some(value) => {
+            encoder.u8(1);
+            encoder.value(value);
+        }
+        None => encoder.u8(0),
+    }
+}
+
+fn decode_optional(decoder: &mut Decoder) -> Result<Option<String>, CodecError> {
+    match decoder.u8()? {
+        0 => Ok(None),
+        1 => Ok(Some(decoder.value()?)),
+        flag => Err(CodecError::UnknownTag(flag)),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KvOutput {
+    /// The key's value before the command ran.
+    Value(Option<String>),
+    /// Whether a `CompareAndSwap` found the expected value and swapped it.
+    Swapped(bool),
+}
+
+/// The replicated map. Learners apply decided commands to it in slot order.
+#[derive(Debug, Clone, Default)]
+pub struct KvStore {
+    entries: BTreeMap<String, String>,
+}
+
+impl KvStore {
+    pub fn new() -> Self {
+        KvStore::default()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.entries.get(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl StateMachine<KvCommand> for KvStore {
+    type Output = KvOutput;
+
+    fn apply(&mut self, _slot: u64, command: &KvCommand) -> KvOutput {
+        match command {
+            KvCommand::Get(key) => KvOutput::Value(self.entries.get(key).cloned()),
+            KvCommand::Put(key, value) => KvOutput::Value(self.entries.insert(key.clone(), value.clone())),
+            KvCommand::Delete(key) => KvOutput::Value(self.entries.remove(key)),
+            KvCommand::CompareAndSwap { key, expected, new } => {
+                if self.entries.get(key) != expected.as_ref() {
+                    return KvOutput::Swapped(false);
+                }
+                match new {
+                    Some(new) => self.entries.insert(key.clone(), new.clone()),
+                    None => self.entries.remove(key),
+                };
+                KvOutput::Swapped(true)
+            }
+        }
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        let mut encoder = Encoder { bytes: vec![] };
+        encoder.u64(self.entries.len() as u64);
+        for (key, value) in &self.entries {
+            encoder.value(key);
+            encoder.value(value);
+        }
+        encoder.bytes
+    }
+
+    fn restore(&mut self, state: &[u8]) -> Result<(), CodecError> {
+        let mut decoder = Decoder { bytes: state };
+        let mut entries = BTreeMap::new();
+        for _ in 0..decoder.u64()? {
+            entries.insert(decoder.value()?, decoder.value()?);
+        }
+        if !decoder.bytes.is_empty() {
+            return Err(CodecError::TrailingBytes);
+        }
+        self.entries = entries;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KvError {
+    /// The command was not applied in time. It may still be applied later.
+    Timeout,
+}
+
+impl fmt::Display for KvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KvError::Timeout => write!(f, "command was not applied in time"),
+        }
+    }
+}
+
+impl std::error::Error for KvError {}
+
+/// Submits commands to a replicated `KvStore` through one proposer and waits
+/// for their results.
+pub struct KvClient {
+    client: Client<Request<KvCommand>>,
+    proposer: Sender<Message<Request<KvCommand>>>,
+    outputs: Outputs<KvOutput>,
+    timeout: Duration,
+}
+
+impl KvClient {
+    /// Client `id`, which must be unique among the store's clients, sending
+    /// to `proposer` and reading results from `outputs`.
+    pub fn new(id: u64, proposer: Sender<Message<Request<KvCommand>>>, outputs: Outputs<KvOutput>) -> Self {
+        KvClient { client: Client::new(id), proposer, outputs, timeout: Duration::from_secs(5) }
+    }
+
+    /// How long each call waits for its command to be applied.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<String>, KvError> {
+        self.value(KvCommand::Get(key.to_string()))
+    }
+
+    /// Sets `key` to `value`, returning the value it replaced.
+    pub fn put(&self, key: &str, value: &str) -> Result<Option<String>, KvError> {
+        self.value(KvCommand::Put(key.to_string(), value.to_string()))
+    }
+
+    /// Removes `key`, returning the value it held.
+    pub fn delete(&self, key: &str) -> Result<Option<String>, KvError> {
+        self.value(KvCommand::Delete(key.to_string()))
+    }
+
+    /// Sets `key` to `new` if it holds `expected`, and reports whether it
+    /// did. `None` stands for a missing key.
+    pub fn compare_and_swap(&self, key: &str, expected: Option<&str>, new: Option<&str>) -> Result<bool, KvError> {
+        let command = KvCommand::CompareAndSwap {
+            key: key.to_string(),
+            expected: expected.map(str::to_string),
+            new: new.map(str::to_string),
+        };
+        match self.execute(command)? {
+            KvOutput::Swapped(swapped) => Ok(swapped),
+            output => unreachable!("CompareAndSwap produced {:?}", output),
+        }
+    }
+
+    fn value(&self, command: KvCommand) -> Result<Option<String>, KvError> {
+        match self.execute(command)? {
+            KvOutput::Value(value) => Ok(value),
+            output => unreachable!("{:?}", output),
+        }
+    }
+
+    fn execute(&self, command: KvCommand) -> Result<KvOutput, KvError> {
+        self.client.execute(command, self.proposer.clone(), &self.outputs, self.timeout).ok_or(KvError::Timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::Cluster;
+
+    #[test]
+    fn test_commands_round_trip() {
+        let commands = vec![
+            KvCommand::Get("a".to_string()),
+            KvCommand::Put("a".to_string(), "∑".to_string()),
+            KvCommand::Delete(String::new()),
+            KvCommand::CompareAndSwap { key: "a".to_string(), expected: None, new: Some("b".to_string()) },
+            KvCommand::CompareAndSwap { key: "a".to_string(), expected: Some("b".to_string()), new: None },
+        ];
+        for command in commands {
+            let mut bytes = vec![];
+            command.encode(&mut bytes);
+            assert_eq!(KvCommand::decode(&bytes), Ok(command));
+            assert!(KvCommand::decode(&bytes[..bytes.len() - 1]).is_err());
+        }
+        assert_eq!(KvCommand::decode(&[9]), Err(CodecError::UnknownTag(9)));
+    }
+
+    #[test]
+    fn test_replicated_store() {
+        let (cluster, outputs) = Cluster::builder().proposers(2).learners(2).build_replicated(KvStore::new());
+        let alice = KvClient::new(0, cluster.proposer(0), outputs.clone());
+        let bob = KvClient::new(1, cluster.proposer(1), outputs);
+
+        assert_eq!(alice.put("x", "1"), Ok(None));
+        assert_eq!(bob.get("x"), Ok(Some("1".to_string())));
+        assert_eq!(bob.compare_and_swap("x", Some("0"), Some("2")), Ok(false));
+        assert_eq!(bob.compare_and_swap("x", Some("1"), Some("2")), Ok(true));
+        assert_eq!(alice.put("x", "3"), Ok(Some("2".to_string())));
+        assert_eq!(alice.compare_and_swap("y", None, Some("4")), Ok(true));
+        assert_eq!(bob.delete("x"), Ok(Some("3".to_string())));
+        assert_eq!(alice.get("x"), Ok(None));
+        assert_eq!(bob.get("y"), Ok(Some("4".to_string())));
+        cluster.shutdown();
+    }
+}
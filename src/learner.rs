@@ -1,27 +1,737 @@
-use crossbeam_channel::{bounded, Receiver, Sender};
+use crate::ballot::Ballot;
+use crate::codec::{CodecError, Decoder, Encoder, MAX_FRAME_LEN};
+use crate::failure_detector::FailureDetector;
 use crate::message::Message;
-use crate::formatting::print_red;
+use crate::formatting::{log, print_red};
+use crate::membership::{Configuration, Membership};
+use crate::quorum::{Phase, QuorumSystem};
+use crate::state_machine::{Outputs, Request, StateMachine};
+use crate::transport::{NodeId, Transport};
+use crate::value::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::slice;
 use std::sync::{Arc, Mutex};
-pub struct Learner {
+use std::time::{Duration, Instant};
+
+/// The decided log, shared between the learners that append to it.
+pub type Storage<V = String> = Arc<Mutex<Log<V>>>;
+
+/// Replicated state after applying every slot below `index`: the last
+/// request applied for each client, then the state machine's own snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub index: u64,
+    pub state: Vec<u8>,
+}
+
+/// Decided `(slot, value)` entries. Slots below the snapshot's index have
+/// been compacted away; the rest are kept in order without gaps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Log<V = String> {
+    snapshot: Option<Snapshot>,
+    entries: Vec<(u64, V)>,
+    /// Configurations decided in the log, kept here rather than in each
+    /// learner so learners sharing the log agree on them. Set by the first
+    /// learner to use the log.
+    membership: Option<Membership>,
+    /// Sequence number of the last request applied for each client, kept
+    /// here for the same reason.
+    applied: BTreeMap<u64, u64>,
+}
+
+impl<V> Default for Log<V> {
+    fn default() -> Self {
+        Log { snapshot: None, entries: vec![], membership: None, applied: BTreeMap::new() }
+    }
+}
+
+impl<V> Log<V> {
+    /// Number of decided slots, compacted ones included, which is also the
+    /// next slot to be decided.
+    pub fn len(&self) -> u64 {
+        self.first() + self.entries.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// First slot whose entry is still kept.
+    pub fn first(&self) -> u64 {
+        self.snapshot.as_ref().map_or(0, |snapshot| snapshot.index)
+    }
+
+    pub fn snapshot(&self) -> Option<&Snapshot> {
+        self.snapshot.as_ref()
+    }
+
+    pub fn membership(&self) -> Option<&Membership> {
+        self.membership.as_ref()
+    }
+
+    /// Entries that have not been compacted away.
+    pub fn entries(&self) -> &[(u64, V)] {
+        &self.entries
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, (u64, V)> {
+        self.entries.iter()
+    }
+
+    /// Kept entries for slots in `start..end`.
+    pub fn range(&self, start: u64, end: u64) -> &[(u64, V)] {
+        let index = |slot: u64| (slot.clamp(self.first(), self.len()) - self.first()) as usize;
+        &self.entries[index(start)..index(end.max(start))]
+    }
+
+    /// Appends the value decided in the next slot.
+    pub fn push(&mut self, value: V) {
+        let slot = self.len();
+        self.entries.push((slot, value));
+    }
+
+    /// Replaces every entry below `snapshot.index` with `snapshot`. The index
+    /// may lie past the end of the log when installing a snapshot from
+    /// another learner.
+    pub fn compact(&mut self, snapshot: Snapshot) {
+        if snapshot.index <= self.first() {
+            return;
+        }
+        self.entries.retain(|(slot, _)| *slot >= snapshot.index);
+        self.snapshot = Some(snapshot);
+    }
+}
+
+/// Most slots asked for in one catch-up request, and answered in one reply.
+pub const CATCH_UP_BATCH: u64 = 1024;
+
+/// Most encoded bytes of entries in one `CatchUpReply`, leaving room below
+/// the codec's frame limit for the rest of the frame.
+const CATCH_UP_REPLY_BYTES: usize = MAX_FRAME_LEN / 2;
+
+/// Most bytes of a snapshot sent in one `Snapshot` message.
+const SNAPSHOT_PART: usize = MAX_FRAME_LEN / 2;
+
+/// Puts a snapshot sent in `Snapshot` parts back together.
+#[derive(Debug, Default)]
+pub(crate) struct SnapshotParts {
+    index: u64,
+    state: Vec<u8>,
+}
+
+impl SnapshotParts {
+    /// Adds the part at `offset` of the snapshot below `index`, `len` bytes
+    /// in all, and returns the snapshot once it is complete. A first part
+    /// starts over; a part that does not continue the bytes so far is
+    /// ignored until then.
+    pub(crate) fn add(&mut self, index: u64, offset: u64, len: u64, part: Vec<u8>) -> Option<Snapshot> {
+        if offset == 0 {
+            self.index = index;
+            self.state.clear();
+        } else if index != self.index || offset != self.state.len() as u64 {
+            return None;
+        }
+        self.state.extend_from_slice(&part);
+        (self.state.len() as u64 >= len).then(|| Snapshot { index, state: std::mem::take(&mut self.state) })
+    }
+}
+
+/// The longest prefix of `entries` whose encoding fits in
+/// `CATCH_UP_REPLY_BYTES`, but at least the first entry.
+fn fit_in_frame<V: Value>(entries: &[(u64, V)]) -> &[(u64, V)] {
+    let mut bytes = vec![];
+    let mut len = 0;
+    for (count, (_, value)) in entries.iter().enumerate() {
+        bytes.clear();
+        value.encode(&mut bytes);
+        // Slot and value length, then the value.
+        len += 16 + bytes.len();
+        if len > CATCH_UP_REPLY_BYTES && count > 0 {
+            return &entries[..count];
+        }
+    }
+    entries
+}
+
+/// A state machine driven by the log, with its command and output types
+/// hidden behind the log's value type. `applied` is the log's last applied
+/// request per client.
+trait Replica<V>: Send {
+    fn apply(&mut self, slot: u64, value: &V, applied: &mut BTreeMap<u64, u64>);
+    fn snapshot(&self, applied: &BTreeMap<u64, u64>) -> Vec<u8>;
+    fn restore(&mut self, state: &[u8], applied: &mut BTreeMap<u64, u64>) -> Result<(), CodecError>;
+}
+
+struct Replicated<S, O> {
+    machine: S,
+    outputs: Outputs<O>,
+}
+
+impl<C: Value, S: StateMachine<C>> Replica<Request<C>> for Replicated<S, S::Output> {
+    /// Skips requests at or below the client's last applied one. A proposer
+    /// that loses track of a chosen value gets it decided again, and it must
+    /// not take effect twice.
+    fn apply(&mut self, slot: u64, request: &Request<C>, applied: &mut BTreeMap<u64, u64>) {
+        if applied.get(&request.client).is_some_and(|seq| request.seq <= *seq) {
+            log!("[Learner] Skipping repeated request {} of client {} in slot {}", request.seq, request.client, slot);
+            return;
+        }
+        applied.insert(request.client, request.seq);
+        let output = self.machine.apply(slot, &request.command);
+        self.outputs.publish(request.client, request.seq, slot, output);
+    }
+
+    fn snapshot(&self, applied: &BTreeMap<u64, u64>) -> Vec<u8> {
+        let mut encoder = Encoder { bytes: vec![] };
+        encoder.u64(applied.len() as u64);
+        for (client, seq) in applied {
+            encoder.u64(*client);
+            encoder.u64(*seq);
+        }
+        encoder.bytes.extend_from_slice(&self.machine.snapshot());
+        encoder.bytes
+    }
+
+    fn restore(&mut self, state: &[u8], applied: &mut BTreeMap<u64, u64>) -> Result<(), CodecError> {
+        let mut decoder = Decoder { bytes: state };
+        let mut restored = BTreeMap::new();
+        for _ in 0..decoder.u64()? {
+            restored.insert(decoder.u64()?, decoder.u64()?);
+        }
+        self.machine.restore(decoder.bytes)?;
+        *applied = restored;
+        Ok(())
+    }
+}
+
+/// Periodic requests for the slots after the end of the local log.
+struct CatchUp {
+    learners: Vec<NodeId>,
+    interval: Duration,
+    next: Instant,
+}
+
+/// Learns decisions by counting acceptors' votes itself, so a faulty
+/// proposer cannot make it record a value that was never chosen.
+pub struct Learner<V = String> {
     id: u64,
+    proposers: Vec<NodeId>,
+    /// Membership the log starts with. The log keeps the current one.
+    membership: Membership,
+    /// Acceptors that voted for each ballot and value, per undecided slot.
+    votes: BTreeMap<u64, BTreeMap<(Ballot, V), BTreeSet<NodeId>>>,
+    pending: BTreeMap<u64, V>,
+    detector: Option<FailureDetector>,
+    catch_up: Option<CatchUp>,
+    /// Applies every entry appended to the log, in slot order.
+    machine: Option<Box<dyn Replica<V>>>,
+    /// Slots between snapshots of `machine`.
+    snapshot_interval: Option<u64>,
+    /// Parts of a snapshot being received from another learner.
+    incoming: SnapshotParts,
+}
+impl<V: Value> Learner<V> {
+    /// A value is decided once a majority of `acceptors` accepted it in the
+    /// same ballot. Decided slots are reported back to `proposers`.
+    pub fn new(id: u64, proposers: Vec<NodeId>, acceptors: Vec<NodeId>) -> Self {
+        Learner {
+            id,
+            proposers,
+            membership: Membership::new(Configuration::new(acceptors, vec![])),
+            votes: BTreeMap::new(),
+            pending: BTreeMap::new(),
+            detector: None,
+            catch_up: None,
+            machine: None,
+            snapshot_interval: None,
+            incoming: SnapshotParts::default(),
+        }
+    }
+
+    /// Every `interval`, starting at `now`, asks the other `learners` and the
+    /// acceptors for the slots after the end of the local log, so decisions
+    /// missed while partitioned or down are recovered.
+    pub fn with_catch_up(mut self, learners: Vec<NodeId>, interval: Duration, now: Instant) -> Self {
+        let learners = learners.into_iter().filter(|learner| *learner != self.id).collect();
+        self.catch_up = Some(CatchUp { learners, interval, next: now });
+        self
+    }
+
+    /// Sends heartbeats and watches peers through `detector`.
+    pub fn with_failure_detector(mut self, detector: FailureDetector) -> Self {
+        self.detector = Some(detector);
+        self
+    }
+
+    /// Decides on a phase-2 quorum of `system` instead of a majority.
+    pub fn with_quorum_system(mut self, system: impl QuorumSystem + 'static) -> Self {
+        self.membership = self.membership.with_quorum_system(system);
+        self
+    }
+
+    /// Takes the acceptors of each slot from `membership`, which the log
+    /// starts with unless another learner already set it. With an `alpha`,
+    /// the learner also tells proposers and acceptors how far the log is
+    /// known, and which configurations it holds.
+    pub fn with_membership(mut self, membership: Membership) -> Self {
+        self.membership = membership;
+        self
+    }
+
+    /// Snapshots the state machine every `slots` decided slots and compacts
+    /// the log and the acceptors' state below the snapshot. Only takes effect
+    /// with a state machine.
+    pub fn with_snapshot_interval(mut self, slots: u64) -> Self {
+        self.snapshot_interval = Some(slots.max(1));
+        self
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn failure_detector(&self) -> Option<&FailureDetector> {
+        self.detector.as_ref()
+    }
+
+    /// Earliest time at which `tick` has work to do.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let heartbeat = self.detector.as_ref().map(FailureDetector::next_deadline);
+        let catch_up = self.catch_up.as_ref().map(|catch_up| catch_up.next);
+        heartbeat.into_iter().chain(catch_up).min()
+    }
+
+    /// Sends heartbeats and catch-up requests that are due. Needs the log to
+    /// know where catching up starts.
+    pub fn tick(&mut self, storage: &Storage<V>, transport: &dyn Transport<V>, now: Instant) {
+        if let Some(detector) = &mut self.detector {
+            detector.tick(None, transport, now);
+        }
+        if let Some(catch_up) = self.catch_up.as_mut().filter(|catch_up| now >= catch_up.next) {
+            catch_up.next = now + catch_up.interval;
+            let mut log = storage.lock().unwrap();
+            let start = log.len();
+            let membership = log.membership.get_or_insert_with(|| self.membership.clone()).clone();
+            drop(log);
+            let request = Message::CatchUp(self.id, start, start.saturating_add(CATCH_UP_BATCH));
+            transport.broadcast(&catch_up.learners, &request);
+            transport.broadcast(&membership.acceptors(start), &request);
+            if membership.alpha().is_some() {
+                transport.broadcast(&self.proposers, &Message::Configure(membership.known(), membership.changes()));
+            }
+        }
+    }
+
+    pub fn handle_heartbeat(&mut self, from: NodeId, now: Instant) {
+        if let Some(detector) = &mut self.detector {
+            detector.heard_from(from, now);
+        }
+    }
+
+    /// Counts `acceptor_id`'s vote for `value` in `slot` and records the value
+    /// once a quorum of the slot's acceptors has voted for it in the same
+    /// ballot. Votes for slots whose configuration is not known yet wait
+    /// until it is.
+    pub fn handle_accepted(
+        &mut self,
+        ballot: Ballot,
+        slot: u64,
+        value: V,
+        acceptor_id: NodeId,
+        storage: &Storage<V>,
+        transport: &dyn Transport<V>,
+    ) {
+        let mut log = storage.lock().unwrap();
+        if slot < log.len() || self.pending.contains_key(&slot) {
+            return;
+        }
+        self.votes.entry(slot).or_default().entry((ballot, value)).or_default().insert(acceptor_id);
+        self.decide(&mut log, transport);
+    }
+
+    /// Adopts what another learner knows of decided slots and configurations.
+    pub fn handle_configure(
+        &mut self,
+        known: u64,
+        changes: Vec<(u64, Configuration)>,
+        storage: &Storage<V>,
+        transport: &dyn Transport<V>,
+    ) {
+        let mut log = storage.lock().unwrap();
+        self.membership(&mut log).merge(known, changes);
+        self.decide(&mut log, transport);
+    }
+
+    /// Records every slot with a quorum of votes among the slots whose
+    /// configuration is known. Each recorded slot may make more known.
+    fn decide(&mut self, log: &mut Log<V>, transport: &dyn Transport<V>) {
+        loop {
+            let membership = self.membership(log);
+            let decided: Vec<(u64, V)> = self
+                .votes
+                .range(..membership.window_end())
+                .filter_map(|(slot, votes)| {
+                    let ((ballot, value), _) =
+                        votes.iter().find(|(_, voters)| membership.is_quorum(*slot, Phase::Two, voters))?;
+                    log!("[Learner] Quorum for slot {} in ballot {}", slot, ballot);
+                    Some((*slot, value.clone()))
+                })
+                .collect();
+            if decided.is_empty() {
+                return;
+            }
+            for (slot, value) in decided {
+                self.record_locked(log, slot, value, transport);
+            }
+        }
+    }
+
+    /// The log's membership, starting it from this learner's if no learner
+    /// has yet.
+    fn membership<'a>(&self, log: &'a mut Log<V>) -> &'a mut Membership {
+        log.membership.get_or_insert_with(|| self.membership.clone())
+    }
+
+    /// Sends learner `from` the decided entries in `start..end` that this
+    /// learner already holds, preceded by the snapshot, in parts, if some of
+    /// them have been compacted away. The configurations decided in the snapshot go
+    /// first. At most `CATCH_UP_BATCH` entries are sent, fewer if they would
+    /// not fit in a frame, and `from` asks again for the rest.
+    pub fn handle_catch_up(&self, from: NodeId, start: u64, end: u64, storage: &Storage<V>, transport: &dyn Transport<V>) {
+        let end = end.min(start.saturating_add(CATCH_UP_BATCH));
+        let log = storage.lock().unwrap();
+        let snapshot = log.snapshot().filter(|snapshot| start < snapshot.index).cloned();
+        let entries = fit_in_frame(log.range(start, end)).to_vec();
+        let membership = log.membership().filter(|membership| membership.alpha().is_some()).cloned();
+        drop(log);
+        if let Some(membership) = membership {
+            transport.broadcast(&[from], &Message::Configure(membership.known(), membership.changes()));
+        }
+        if let Some(snapshot) = snapshot {
+            let len = snapshot.state.len();
+            let mut offset = 0;
+            loop {
+                let end = len.min(offset + SNAPSHOT_PART);
+                let part = snapshot.state[offset..end].to_vec();
+                transport.broadcast(&[from], &Message::Snapshot(snapshot.index, offset as u64, len as u64, part));
+                offset = end;
+                if offset == len {
+                    break;
+                }
+            }
+        }
+        if !entries.is_empty() {
+            transport.broadcast(&[from], &Message::CatchUpReply(entries));
+        }
+    }
+
+    /// Collects a part of another learner's snapshot covering every slot
+    /// below `index`. Once every part is in, installs the snapshot if it
+    /// reaches past the end of the local log, and the configurations decided
+    /// in those slots are known.
+    pub fn handle_snapshot(
+        &mut self,
+        index: u64,
+        offset: u64,
+        len: u64,
+        part: Vec<u8>,
+        storage: &Storage<V>,
+        transport: &dyn Transport<V>,
+    ) {
+        let Some(Snapshot { index, state }) = self.incoming.add(index, offset, len, part) else { return };
+        let mut log = storage.lock().unwrap();
+        let len = log.len();
+        let membership = self.membership(&mut log);
+        if index <= len || membership.alpha().is_some() && membership.known() < index {
+            return;
+        }
+        if let Some(machine) = &mut self.machine {
+            if let Err(error) = machine.restore(&state, &mut log.applied) {
+                print_red(&format!("[Learner] Rejected snapshot up to slot {}: {}", index, error));
+                return;
+            }
+        }
+        log!("[Learner] Installing snapshot up to slot {}", index);
+        log.compact(Snapshot { index, state });
+        self.votes = self.votes.split_off(&index);
+        self.pending = self.pending.split_off(&index);
+        self.append_pending(&mut log, transport);
+        transport.broadcast(&self.proposers, &Message::Decided(index - 1));
+    }
+
+    /// Records the decided value for `slot`, whether learned from a quorum of
+    /// votes or from another learner. Decisions that arrive ahead of a gap
+    /// are held back until every earlier slot is known.
+    pub fn record(&mut self, slot: u64, value: V, storage: &Storage<V>, transport: &dyn Transport<V>) {
+        let mut log = storage.lock().unwrap();
+        self.record_locked(&mut log, slot, value, transport);
+        self.decide(&mut log, transport);
+    }
+
+    fn record_locked(&mut self, log: &mut Log<V>, slot: u64, value: V, transport: &dyn Transport<V>) {
+        if slot < log.len() || self.pending.contains_key(&slot) {
+            return;
+        }
+        print_red(&format!("[Learner] Recording value: {:?} in slot: {:?}", value, slot));
+        self.votes.remove(&slot);
+        self.pending.insert(slot, value);
+        self.append_pending(log, transport);
+        transport.broadcast(&self.proposers, &Message::Decided(slot));
+    }
+
+    /// Moves pending decisions that extend the log into it, applying each to
+    /// the state machine and snapshotting it when an interval is reached.
+    /// Decided configurations join the membership, and with an `alpha`
+    /// proposers hear how far the log reaches. The acceptors and learners of
+    /// every configuration hear of a new one.
+    fn append_pending(&mut self, log: &mut Log<V>, transport: &dyn Transport<V>) {
+        let start = log.len();
+        let mut reconfigured = false;
+        while let Some(value) = self.pending.remove(&log.len()) {
+            let slot = log.len();
+            if let Some(config) = value.reconfiguration() {
+                reconfigured |= self.membership(log).reconfigure(slot, config.clone());
+            }
+            let Some(machine) = &mut self.machine else {
+                log.push(value);
+                continue;
+            };
+            machine.apply(slot, &value, &mut log.applied);
+            log.push(value);
+            let index = log.len();
+            if self.snapshot_interval.is_some_and(|interval| index.is_multiple_of(interval)) {
+                log!("[Learner] Compacting log below slot {}", index);
+                let state = machine.snapshot(&log.applied);
+                log.compact(Snapshot { index, state });
+                transport.broadcast(&self.membership(log).acceptors(0), &Message::Compact(index));
+            }
+        }
+        let len = log.len();
+        let membership = self.membership(log);
+        membership.advance(len);
+        if membership.alpha().is_none() || len == start {
+            return;
+        }
+        let message = Message::Configure(membership.known(), membership.changes());
+        transport.broadcast(&self.proposers, &message);
+        if reconfigured {
+            let mut nodes = membership.acceptors(0);
+            nodes.extend(membership.learners(0).into_iter().filter(|learner| *learner != self.id));
+            transport.broadcast(&nodes, &message);
+        }
+    }
 }
-impl Learner {
-    pub fn new(id: u64) -> Self {
-        Learner { id }
+
+impl<C: Value> Learner<Request<C>> {
+    /// Applies each request to `machine` as it joins the log and publishes the
+    /// output to `outputs`. Entries are applied while the log is locked, so a
+    /// machine shared by learners that share a log sees every slot once, in
+    /// order. A request decided again, or after a later one of its client, is
+    /// not applied.
+    pub fn with_state_machine<S: StateMachine<C>>(mut self, machine: S, outputs: Outputs<S::Output>) -> Self {
+        self.machine = Some(Box::new(Replicated { machine, outputs }));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proposer::Proposer;
+    use crate::transport::tests::drain;
+    use crate::transport::ChannelTransport;
+
+    #[test]
+    fn test_log_has_no_gaps() {
+        let nodes: Vec<ChannelTransport> = ChannelTransport::network(&[0]);
+        let mut learner = Learner::new(0, vec![], vec![]);
+        let storage = Storage::default();
+
+        learner.record(1, "b".to_string(), &storage, &nodes[0]);
+        assert!(storage.lock().unwrap().is_empty());
+
+        learner.record(0, "a".to_string(), &storage, &nodes[0]);
+        learner.record(0, "a".to_string(), &storage, &nodes[0]);
+        assert_eq!(storage.lock().unwrap().entries(), [(0, "a".to_string()), (1, "b".to_string())]);
+    }
+
+    #[test]
+    fn test_decides_on_a_quorum_of_votes_in_one_ballot() {
+        let nodes: Vec<ChannelTransport> = ChannelTransport::network(&[0, 1]);
+        let mut learner = Learner::new(1, vec![0], vec![2, 3, 4]);
+        let storage = Storage::default();
+        let vote = |learner: &mut Learner, round: u64, value: &str, acceptor: NodeId| {
+            learner.handle_accepted(Ballot::new(round, 0), 0, value.to_string(), acceptor, &storage, &nodes[1]);
+        };
+
+        vote(&mut learner, 1, "a", 2);
+        vote(&mut learner, 1, "a", 2);
+        vote(&mut learner, 2, "a", 3);
+        vote(&mut learner, 1, "a", 9);
+        assert!(storage.lock().unwrap().is_empty());
+
+        vote(&mut learner, 2, "a", 4);
+        assert_eq!(storage.lock().unwrap().entries(), [(0, "a".to_string())]);
+        assert!(matches!(drain(&nodes[0])[..], [Message::Decided(0)]));
+    }
+
+    /// Appends every command it sees as `slot=command`, so the test can
+    /// check the order.
+    struct Journal(Vec<String>);
+
+    impl StateMachine<String> for Journal {
+        type Output = usize;
+
+        fn apply(&mut self, slot: u64, command: &String) -> usize {
+            self.0.push(format!("{}={}", slot, command));
+            self.0.len()
+        }
+
+        fn snapshot(&self) -> Vec<u8> {
+            self.0.join(",").into_bytes()
+        }
+
+        fn restore(&mut self, state: &[u8]) -> Result<(), CodecError> {
+            let state = std::str::from_utf8(state).map_err(|_| CodecError::InvalidUtf8)?;
+            self.0 = state.split(',').filter(|entry| !entry.is_empty()).map(str::to_string).collect();
+            Ok(())
+        }
     }
 
-    pub fn record(&self, proposal_number: u64, round_number: u64, value: String, storage: &mut Arc<Mutex<Vec<(u64, String)>>>, txs: &Vec<Sender<Message>>) {
-        print_red(&format!("[Learner] Recording value: {:?} with proposal number: {:?} and round number: {:?}", value, proposal_number, round_number));
-        let mut storage_guard: std::sync::MutexGuard<'_, Vec<(u64, String)>> = storage.lock().unwrap();
-        if !storage_guard.contains(&(round_number, value.clone())) {
-            storage_guard.push((round_number, value.clone()));
-            self.update_round_number(round_number, &txs);
+    fn request(seq: u64, command: &str) -> Request<String> {
+        Request { client: 7, seq, command: command.to_string() }
+    }
+
+    #[test]
+    fn test_state_machine_applies_each_slot_once_in_order() {
+        let nodes: Vec<ChannelTransport<Request<String>>> = ChannelTransport::network(&[0]);
+        let journal = Arc::new(Mutex::new(Journal(vec![])));
+        let outputs = Outputs::new();
+        let mut learner = Learner::new(0, vec![], vec![]).with_state_machine(journal.clone(), outputs.clone());
+        let storage = Storage::default();
+
+        learner.record(2, request(2, "c"), &storage, &nodes[0]);
+        learner.record(1, request(1, "b"), &storage, &nodes[0]);
+        assert!(journal.lock().unwrap().0.is_empty());
+        assert_eq!(outputs.get(7, 1), None);
+
+        learner.record(0, request(0, "a"), &storage, &nodes[0]);
+        learner.record(1, request(1, "b"), &storage, &nodes[0]);
+        assert_eq!(journal.lock().unwrap().0, ["0=a", "1=b", "2=c"]);
+        assert_eq!(outputs.get(7, 2), Some((2, 3)));
+    }
+
+    #[test]
+    fn test_request_decided_again_after_compaction_is_applied_once() {
+        let nodes: Vec<ChannelTransport<Request<String>>> = ChannelTransport::network(&[0, 1, 2]);
+        let now = Instant::now();
+        let mut proposer = Proposer::new(0, vec![1]);
+        let journal = Arc::new(Mutex::new(Journal(vec![])));
+        let outputs = Outputs::new();
+        let mut learner = Learner::new(2, vec![], vec![1]).with_state_machine(journal.clone(), outputs.clone());
+        let storage = Storage::default();
+
+        proposer.handle_consensus(&nodes[0], None, request(0, "a"), now);
+        let Message::PrepareAll(ballot, from) = drain(&nodes[1]).remove(0) else { panic!() };
+        proposer.handle_promise_all(1, ballot, from, vec![], &nodes[0], now);
+        let Message::Propose(ballot, 0, value) = drain(&nodes[1]).remove(0) else { panic!() };
+        // The value is chosen, but the acceptor's `Accept` is lost and the
+        // slot compacted away before the proposer retries.
+        learner.handle_accepted(ballot, 0, value, 1, &storage, &nodes[2]);
+        proposer.handle_compact(1, &nodes[0], now);
+        let Message::Propose(ballot, 1, value) = drain(&nodes[1]).remove(0) else { panic!() };
+        learner.handle_accepted(ballot, 1, value, 1, &storage, &nodes[2]);
+
+        assert_eq!(storage.lock().unwrap().len(), 2);
+        assert_eq!(journal.lock().unwrap().0, ["0=a"]);
+        assert_eq!(outputs.get(7, 0), Some((0, 1)));
+    }
+
+    #[test]
+    fn test_catch_up_replies_fit_in_a_frame() {
+        let nodes: Vec<ChannelTransport<Vec<u8>>> = ChannelTransport::network(&[0, 1]);
+        let learner = Learner::new(0, vec![], vec![]);
+        let storage = Storage::default();
+        for _ in 0..CATCH_UP_BATCH + 5 {
+            storage.lock().unwrap().push(vec![]);
         }
+        learner.handle_catch_up(1, 0, u64::MAX, &storage, &nodes[0]);
+        let [Message::CatchUpReply(entries)] = &drain(&nodes[1])[..] else { panic!() };
+        assert_eq!(entries.len() as u64, CATCH_UP_BATCH);
+
+        let large = vec![0; MAX_FRAME_LEN / 3];
+        storage.lock().unwrap().push(large.clone());
+        storage.lock().unwrap().push(large);
+        let start = CATCH_UP_BATCH + 5;
+        learner.handle_catch_up(1, start, u64::MAX, &storage, &nodes[0]);
+        let [Message::CatchUpReply(entries)] = &drain(&nodes[1])[..] else { panic!() };
+        assert_eq!(entries.len(), 1);
+        learner.handle_catch_up(1, start + 1, u64::MAX, &storage, &nodes[0]);
+        let [Message::CatchUpReply(entries)] = &drain(&nodes[1])[..] else { panic!() };
+        assert_eq!(entries[0].0, start + 1);
     }
 
-    fn update_round_number(&self, round_number: u64, txs: &Vec<Sender<Message>>) {
-        for acceptor in txs.iter() {
-            acceptor.send(Message::RoundNumber(round_number+1)).unwrap();
+    #[test]
+    fn test_large_snapshots_are_sent_in_parts() {
+        let nodes: Vec<ChannelTransport<Vec<u8>>> = ChannelTransport::network(&[0, 1]);
+        let learner = Learner::new(0, vec![], vec![]);
+        let storage = Storage::default();
+        for _ in 0..3 {
+            storage.lock().unwrap().push(vec![]);
+        }
+        let state = vec![7; MAX_FRAME_LEN];
+        storage.lock().unwrap().compact(Snapshot { index: 3, state: state.clone() });
+
+        learner.handle_catch_up(1, 0, CATCH_UP_BATCH, &storage, &nodes[0]);
+        let parts = drain(&nodes[1]);
+        assert_eq!(parts.len(), 2);
+        let mut lagging = Learner::new(1, vec![], vec![]);
+        let lagging_storage = Storage::default();
+        for part in parts {
+            assert!(crate::codec::encode_frame(&part).is_ok());
+            let Message::Snapshot(index, offset, len, part) = part else { panic!() };
+            lagging.handle_snapshot(index, offset, len, part, &lagging_storage, &nodes[1]);
         }
+        assert_eq!(lagging_storage.lock().unwrap().snapshot(), Some(&Snapshot { index: 3, state }));
+    }
+
+    #[test]
+    fn test_lagging_learner_installs_snapshot() {
+        let nodes: Vec<ChannelTransport<Request<String>>> = ChannelTransport::network(&[0, 1, 2]);
+        let mut learner = Learner::new(0, vec![], vec![2])
+            .with_state_machine(Journal(vec![]), Outputs::new())
+            .with_snapshot_interval(2);
+        let storage = Storage::default();
+        for (slot, command) in ["a", "b", "c", "d", "e"].into_iter().enumerate() {
+            learner.record(slot as u64, request(slot as u64, command), &storage, &nodes[0]);
+        }
+        assert_eq!(storage.lock().unwrap().first(), 4);
+        assert_eq!(storage.lock().unwrap().entries(), [(4, request(4, "e"))]);
+        assert_eq!(drain(&nodes[2]), vec![Message::Compact(2), Message::Compact(4)]);
+
+        let journal = Arc::new(Mutex::new(Journal(vec![])));
+        let mut lagging = Learner::new(1, vec![], vec![2]).with_state_machine(journal.clone(), Outputs::new());
+        let lagging_storage = Storage::default();
+        lagging.record(0, request(0, "a"), &lagging_storage, &nodes[1]);
+        learner.handle_catch_up(1, 1, CATCH_UP_BATCH, &storage, &nodes[0]);
+        for message in drain(&nodes[1]) {
+            match message {
+                Message::Snapshot(index, offset, len, part) => {
+                    lagging.handle_snapshot(index, offset, len, part, &lagging_storage, &nodes[1])
+                }
+                Message::CatchUpReply(entries) => {
+                    for (slot, value) in entries {
+                        lagging.record(slot, value, &lagging_storage, &nodes[1]);
+                    }
+                }
+                message => panic!("unexpected {:?}", message),
+            }
+        }
+
+        assert_eq!(lagging_storage.lock().unwrap().len(), 5);
+        assert_eq!(journal.lock().unwrap().0, ["0=a", "1=b", "2=c", "3=d", "4=e"]);
+        // The snapshot carries which requests were applied.
+        lagging.record(5, request(2, "c"), &lagging_storage, &nodes[1]);
+        assert_eq!(journal.lock().unwrap().0.len(), 5);
     }
 }
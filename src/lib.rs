@@ -0,0 +1,50 @@
+//! Simple Paxos implementation via multithreading.
+//!
+//! The consensus roles (`Proposer`, `Acceptor`, `Learner`) and the `Message`
+//! type are exported so they can be embedded in other binaries. Nodes talk
+//! through a `Transport`: `Cluster` wires a set of nodes together over
+//! in-process channels, while `TcpTransport` lets each node run in its own
+//! process. Everything is generic over the `Value` being agreed on, with
+//! `String` as the default.
+pub mod acceptor;
+pub mod ballot;
+pub mod client;
+pub mod cluster;
+pub mod codec;
+pub mod failure_detector;
+pub mod fault;
+pub mod history;
+pub mod kv;
+pub mod learner;
+pub mod linearizability;
+pub mod manifest;
+pub mod membership;
+pub mod message;
+pub mod model_check;
+pub mod node;
+pub mod proposer;
+pub mod quorum;
+pub mod remote;
+pub mod rng;
+pub mod sim;
+pub mod state_machine;
+pub mod tcp;
+pub mod transport;
+pub mod value;
+pub mod wal;
+mod formatting;
+
+pub use acceptor::Acceptor;
+pub use ballot::Ballot;
+pub use client::Client;
+pub use cluster::{Cluster, ClusterBuilder};
+pub use kv::{KvClient, KvStore};
+pub use learner::Learner;
+pub use membership::{Command, Configuration, Membership};
+pub use message::Message;
+pub use proposer::Proposer;
+pub use quorum::QuorumSystem;
+pub use state_machine::StateMachine;
+pub use tcp::TcpTransport;
+pub use transport::{ChannelTransport, Network, NodeId, Transport};
+pub use value::Value;
@@ -0,0 +1,230 @@
+//! Linearizability checking for recorded client histories.
+//!
+//! `check` searches for an order of the operations in a `History` that
+//! respects real time (an operation that completed before another was
+//! invoked comes first) and that a sequential `Model` accepts, in the style
+//! of Wing & Gong with Porcupine's memoization of visited states. Pending
+//! operations may be placed anywhere after their invocation or left out.
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::Hash;
+use crate::history::{History, Operation};
+
+/// Sequential specification of an object.
+pub trait Model {
+    type State: Clone + Eq + Hash;
+    type Input;
+    type Output;
+
+    fn init(&self) -> Self::State;
+
+    /// Applies `input` to `state`. Returns the new state, or `None` if the
+    /// object could not have returned `output`. A missing `output` belongs to
+    /// a pending operation and matches anything.
+    fn step(&self, state: &Self::State, input: &Self::Input, output: Option<&Self::Output>) -> Option<Self::State>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RegisterOp {
+    Read,
+    Write(String),
+}
+
+/// A single read/write register, initially empty. Reads return the current
+/// value; the output of a write is ignored.
+pub struct Register;
+
+impl Model for Register {
+    type State = Option<String>;
+    type Input = RegisterOp;
+    type Output = Option<String>;
+
+    fn init(&self) -> Self::State {
+        None
+    }
+
+    fn step(&self, state: &Self::State, input: &RegisterOp, output: Option<&Option<String>>) -> Option<Self::State> {
+        match input {
+            RegisterOp::Write(value) => Some(Some(value.clone())),
+            RegisterOp::Read if output.is_none_or(|read| read == state) => Some(state.clone()),
+            RegisterOp::Read => None,
+        }
+    }
+}
+
+/// The decided log seen as an append-only sequence: appending a value returns
+/// the slot it landed in, and slots are handed out in order.
+pub struct AppendLog;
+
+impl Model for AppendLog {
+    type State = u64;
+    type Input = String;
+    type Output = u64;
+
+    fn init(&self) -> u64 {
+        0
+    }
+
+    fn step(&self, len: &u64, _value: &String, slot: Option<&u64>) -> Option<u64> {
+        slot.is_none_or(|slot| slot == len).then_some(len + 1)
+    }
+}
+
+/// Completed operations that cannot be linearized even when every other
+/// operation is treated as pending, and none of which can be made pending
+/// without the rest becoming linearizable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Counterexample<I, O> {
+    pub operations: Vec<Operation<I, O>>,
+}
+
+impl<I: fmt::Debug, O: fmt::Debug> fmt::Display for Counterexample<I, O> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "history is not linearizable:")?;
+        for op in &self.operations {
+            let complete = op.complete.map_or("pending".to_string(), |at| format!("{:?}", at));
+            writeln!(f, "  client {} {:?} -> {:?} [{:?}, {}]", op.client, op.input, op.output, op.invoke, complete)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks `history` against `model`. On success returns the indices of the
+/// linearized operations in linearization order; otherwise returns a minimal
+/// counterexample.
+pub fn check<M>(model: &M, history: &History<M::Input, M::Output>) -> Result<Vec<usize>, Counterexample<M::Input, M::Output>>
+where
+    M: Model,
+    M::Input: Clone,
+    M::Output: Clone,
+{
+    if let Some(order) = linearize(model, history.operations()) {
+        return Ok(order);
+    }
+    // Making an operation pending only loosens the constraints, so a single
+    // pass leaves each remaining operation necessary for the failure.
+    let mut relaxed = history.operations().to_vec();
+    for i in 0..relaxed.len() {
+        if relaxed[i].complete.is_none() {
+            continue;
+        }
+        let completed = relaxed[i].clone();
+        relaxed[i].output = None;
+        relaxed[i].complete = None;
+        if linearize(model, &relaxed).is_some() {
+            relaxed[i] = completed;
+        }
+    }
+    Err(Counterexample { operations: relaxed.into_iter().filter(|op| op.complete.is_some()).collect() })
+}
+
+fn linearize<M: Model>(model: &M, operations: &[Operation<M::Input, M::Output>]) -> Option<Vec<usize>> {
+    let mut search = Search { model, operations, done: vec![false; operations.len()], order: vec![], visited: HashSet::new() };
+    search.run(model.init()).then_some(search.order)
+}
+
+struct Search<'a, M: Model> {
+    model: &'a M,
+    operations: &'a [Operation<M::Input, M::Output>],
+    done: Vec<bool>,
+    order: Vec<usize>,
+    visited: HashSet<(Vec<bool>, M::State)>,
+}
+
+impl<M: Model> Search<'_, M> {
+    fn run(&mut self, state: M::State) -> bool {
+        let remaining = || self.operations.iter().zip(&self.done).filter(|(_, done)| !**done).map(|(op, _)| op);
+        if remaining().all(|op| op.complete.is_none()) {
+            return true;
+        }
+        if !self.visited.insert((self.done.clone(), state.clone())) {
+            return false;
+        }
+        // Whatever goes next must have started before every remaining
+        // operation finished.
+        let horizon = remaining().filter_map(|op| op.complete).min();
+        for i in 0..self.operations.len() {
+            let op = &self.operations[i];
+            if self.done[i] || horizon.is_some_and(|horizon| op.invoke > horizon) {
+                continue;
+            }
+            let Some(next) = self.model.step(&state, &op.input, op.output.as_ref()) else {
+                continue;
+            };
+            self.done[i] = true;
+            self.order.push(i);
+            if self.run(next) {
+                return true;
+            }
+            self.done[i] = false;
+            self.order.pop();
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn write(history: &mut History<RegisterOp, Option<String>>, client: u64, value: &str, invoke: u64, complete: u64) {
+        let index = history.invoke(client, RegisterOp::Write(value.to_string()), ms(invoke));
+        history.complete(index, None, ms(complete));
+    }
+
+    fn read(history: &mut History<RegisterOp, Option<String>>, client: u64, value: Option<&str>, invoke: u64, complete: u64) {
+        let index = history.invoke(client, RegisterOp::Read, ms(invoke));
+        history.complete(index, value.map(str::to_string), ms(complete));
+    }
+
+    #[test]
+    fn test_concurrent_register_history_is_linearizable() {
+        let mut history = History::new();
+        write(&mut history, 0, "a", 0, 10);
+        write(&mut history, 1, "b", 5, 20);
+        read(&mut history, 2, Some("b"), 6, 8);
+        read(&mut history, 2, Some("a"), 9, 15);
+        history.invoke(3, RegisterOp::Write("c".to_string()), ms(1));
+
+        // Both writes overlap the reads, so "b" then "a" is possible; the
+        // pending write of "c" never took effect.
+        assert_eq!(check(&Register, &history), Ok(vec![1, 2, 0, 3]));
+    }
+
+    #[test]
+    fn test_stale_read_reports_minimal_counterexample() {
+        let mut history = History::new();
+        write(&mut history, 0, "a", 0, 10);
+        read(&mut history, 1, Some("a"), 11, 12);
+        write(&mut history, 0, "b", 20, 30);
+        read(&mut history, 1, Some("a"), 40, 50);
+
+        let counterexample = check(&Register, &history).unwrap_err();
+        let inputs: Vec<_> = counterexample.operations.iter().map(|op| op.input.clone()).collect();
+        // The first read already pins the write of "a" before the write of
+        // "b", so the write of "a" can be left pending.
+        assert_eq!(inputs, vec![RegisterOp::Read, RegisterOp::Write("b".to_string()), RegisterOp::Read]);
+        assert!(counterexample.to_string().contains("Write(\"b\")"));
+    }
+
+    #[test]
+    fn test_append_log_respects_real_time() {
+        let mut history = History::new();
+        let first = history.invoke(0, "a".to_string(), ms(0));
+        let second = history.invoke(1, "b".to_string(), ms(20));
+        history.complete(second, 0, ms(30));
+        history.complete(first, 1, ms(10));
+
+        // "a" finished before "b" started, so nothing could fill slot 0 first.
+        let counterexample = check(&AppendLog, &history).unwrap_err();
+        assert_eq!(counterexample.operations, vec![history.operations()[first].clone()]);
+
+        history.complete(first, 1, ms(25));
+        assert_eq!(check(&AppendLog, &history), Ok(vec![1, 0]));
+    }
+}
@@ -0,0 +1,356 @@
+//! Cluster manifests: which nodes a TCP cluster has, where they listen and
+//! how they are tuned, read from a TOML file so a cluster can be reshaped
+//! without recompiling.
+//!
+//! ```toml
+//! storage_dir = "data"
+//!
+//! [timeouts]
+//! phase_ms = 500
+//! heartbeat_interval_ms = 100
+//!
+//! [[nodes]]
+//! id = 0
+//! role = "proposer"
+//! address = "127.0.0.1:7000"
+//! ```
+//!
+//! Every timeout is optional and defaults to the value the library uses; none
+//! may be zero.
+//! Nodes with the `client` role are not run as part of the cluster; they give
+//! `paxos-cli` an address that the nodes can answer.
+use serde::Deserialize;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use crate::acceptor::Acceptor;
+use crate::failure_detector::{FailureDetector, HeartbeatConfig};
+use crate::learner::{Learner, Storage};
+use crate::node::{run_acceptor, run_learner, run_proposer};
+use crate::proposer::{Proposer, RetryConfig};
+use crate::tcp::TcpTransport;
+use crate::transport::NodeId;
+use crate::value::Value;
+use crate::wal::FileStorage;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Proposer,
+    Acceptor,
+    Learner,
+    Client,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Role::Proposer => write!(f, "proposer"),
+            Role::Acceptor => write!(f, "acceptor"),
+            Role::Learner => write!(f, "learner"),
+            Role::Client => write!(f, "client"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NodeConfig {
+    pub id: NodeId,
+    pub role: Role,
+    /// Address the node listens on and its peers connect to.
+    pub address: SocketAddr,
+    /// Directory for this acceptor's log, overriding the manifest's.
+    pub storage_dir: Option<PathBuf>,
+}
+
+/// Timeouts in milliseconds, see `RetryConfig` and `HeartbeatConfig`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timeouts {
+    pub phase_ms: u64,
+    pub backoff_base_ms: u64,
+    pub backoff_max_ms: u64,
+    pub max_retries: u32,
+    pub heartbeat_interval_ms: u64,
+    pub heartbeat_timeout_ms: u64,
+    /// Time between a learner's catch-up requests.
+    pub catch_up_ms: u64,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        let retry = RetryConfig::default();
+        let heartbeats = HeartbeatConfig::default();
+        Timeouts {
+            phase_ms: retry.timeout.as_millis() as u64,
+            backoff_base_ms: retry.backoff_base.as_millis() as u64,
+            backoff_max_ms: retry.backoff_max.as_millis() as u64,
+            max_retries: retry.max_retries,
+            heartbeat_interval_ms: heartbeats.interval.as_millis() as u64,
+            heartbeat_timeout_ms: heartbeats.timeout.as_millis() as u64,
+            catch_up_ms: 1000,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ManifestError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    /// Two nodes share an id.
+    DuplicateId(NodeId),
+    /// No node has this role, so the cluster cannot decide anything.
+    MissingRole(Role),
+    /// The manifest has no node with this id.
+    UnknownNode(NodeId),
+    /// The node exists but does not have the role it was needed for.
+    WrongRole(NodeId, Role),
+    /// The node is a client, which `paxos-cli` runs rather than the cluster.
+    ClientNode(NodeId),
+    /// The named timeout is zero, which would make nodes spin.
+    ZeroTimeout(&'static str),
+    /// Node ids run up to the largest one, leaving none for a client.
+    NoClientId,
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ManifestError::Io(error) => write!(f, "{}", error),
+            ManifestError::Parse(error) => write!(f, "invalid manifest: {}", error),
+            ManifestError::DuplicateId(id) => write!(f, "node id {} is used more than once", id),
+            ManifestError::MissingRole(role) => write!(f, "manifest has no {}", role),
+            ManifestError::UnknownNode(id) => write!(f, "manifest has no node {}", id),
+            ManifestError::WrongRole(id, role) => write!(f, "node {} is not a {}", id, role),
+            ManifestError::ClientNode(id) => write!(f, "node {} is a client", id),
+            ManifestError::ZeroTimeout(name) => write!(f, "timeouts.{} must be greater than zero", name),
+            ManifestError::NoClientId => write!(f, "no node id is left for a client"),
+        }
+    }
+}
+
+impl std::error::Error for ManifestError {}
+
+impl From<io::Error> for ManifestError {
+    fn from(error: io::Error) -> Self {
+        ManifestError::Io(error)
+    }
+}
+
+impl From<toml::de::Error> for ManifestError {
+    fn from(error: toml::de::Error) -> Self {
+        ManifestError::Parse(error)
+    }
+}
+
+/// The nodes of a TCP cluster and how to run them.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// Directory holding the acceptors' logs. Acceptors keep their state in
+    /// memory only when neither this nor their own `storage_dir` is set.
+    pub storage_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub timeouts: Timeouts,
+    pub nodes: Vec<NodeConfig>,
+}
+
+impl Manifest {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ManifestError> {
+        Manifest::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Parses a manifest and checks that it describes a usable cluster.
+    pub fn parse(text: &str) -> Result<Self, ManifestError> {
+        let manifest: Manifest = toml::from_str(text)?;
+        for (index, node) in manifest.nodes.iter().enumerate() {
+            if manifest.nodes[..index].iter().any(|other| other.id == node.id) {
+                return Err(ManifestError::DuplicateId(node.id));
+            }
+        }
+        for role in [Role::Proposer, Role::Acceptor, Role::Learner] {
+            if manifest.ids(role).is_empty() {
+                return Err(ManifestError::MissingRole(role));
+            }
+        }
+        let timeouts = &manifest.timeouts;
+        let durations = [
+            ("phase_ms", timeouts.phase_ms),
+            ("backoff_base_ms", timeouts.backoff_base_ms),
+            ("backoff_max_ms", timeouts.backoff_max_ms),
+            ("heartbeat_interval_ms", timeouts.heartbeat_interval_ms),
+            ("heartbeat_timeout_ms", timeouts.heartbeat_timeout_ms),
+            ("catch_up_ms", timeouts.catch_up_ms),
+        ];
+        if let Some((name, _)) = durations.into_iter().find(|(_, ms)| *ms == 0) {
+            return Err(ManifestError::ZeroTimeout(name));
+        }
+        Ok(manifest)
+    }
+
+    pub fn node(&self, id: NodeId) -> Result<&NodeConfig, ManifestError> {
+        self.nodes.iter().find(|node| node.id == id).ok_or(ManifestError::UnknownNode(id))
+    }
+
+    /// Ids of the nodes with `role`, in manifest order.
+    pub fn ids(&self, role: Role) -> Vec<NodeId> {
+        self.nodes.iter().filter(|node| node.role == role).map(|node| node.id).collect()
+    }
+
+    /// An id above every node of the manifest, for clients.
+    pub fn client_id(&self) -> Result<NodeId, ManifestError> {
+        match self.nodes.iter().map(|node| node.id).max() {
+            Some(id) => id.checked_add(1).ok_or(ManifestError::NoClientId),
+            None => Ok(0),
+        }
+    }
+
+    pub fn retry(&self) -> RetryConfig {
+        RetryConfig {
+            timeout: Duration::from_millis(self.timeouts.phase_ms),
+            backoff_base: Duration::from_millis(self.timeouts.backoff_base_ms),
+            backoff_max: Duration::from_millis(self.timeouts.backoff_max_ms),
+            max_retries: self.timeouts.max_retries,
+        }
+    }
+
+    pub fn heartbeats(&self) -> HeartbeatConfig {
+        HeartbeatConfig {
+            interval: Duration::from_millis(self.timeouts.heartbeat_interval_ms),
+            timeout: Duration::from_millis(self.timeouts.heartbeat_timeout_ms),
+        }
+    }
+
+    /// Listens on `address` as node `id` and knows where every node of the
+    /// manifest listens.
+    pub fn transport<V: Value>(&self, id: NodeId, address: SocketAddr) -> Result<TcpTransport<V>, ManifestError> {
+        let transport = TcpTransport::bind(id, address)?;
+        for node in &self.nodes {
+            transport.add_peer(node.id, node.address);
+        }
+        Ok(transport)
+    }
+
+    fn detector(&self, id: NodeId, now: Instant) -> FailureDetector {
+        let peers = self.nodes.iter().filter(|node| node.role != Role::Client).map(|node| node.id).collect();
+        FailureDetector::new(id, peers, self.heartbeats(), now)
+    }
+
+    pub fn proposer<V: Value>(&self, id: NodeId, now: Instant) -> Proposer<V> {
+        Proposer::new(id, self.ids(Role::Acceptor))
+            .with_retry_config(self.retry())
+            .with_failure_detector(self.detector(id, now))
+    }
+
+    /// Acceptor `id`, recovered from its log when it has a storage
+    /// directory.
+    pub fn acceptor<V: Value>(&self, id: NodeId, now: Instant) -> Result<Acceptor<V>, ManifestError> {
+        let acceptor = match self.node(id)?.storage_dir.as_ref().or(self.storage_dir.as_ref()) {
+            Some(dir) => {
+                std::fs::create_dir_all(dir)?;
+                let storage = FileStorage::open(dir.join(format!("acceptor-{}.wal", id)))?;
+                Acceptor::with_storage(id, Box::new(storage))?
+            }
+            None => Acceptor::new(id),
+        };
+        Ok(acceptor.with_learners(self.ids(Role::Learner)).with_failure_detector(self.detector(id, now)))
+    }
+
+    pub fn learner<V: Value>(&self, id: NodeId, now: Instant) -> Learner<V> {
+        let catch_up = Duration::from_millis(self.timeouts.catch_up_ms);
+        Learner::new(id, self.ids(Role::Proposer), self.ids(Role::Acceptor))
+            .with_catch_up(self.ids(Role::Learner), catch_up, now)
+            .with_failure_detector(self.detector(id, now))
+    }
+
+    /// Starts node `id` on its own thread, listening on its address. A
+    /// learner appends its decisions to `storage`. Clients cannot be
+    /// started this way.
+    pub fn spawn<V: Value>(&self, id: NodeId, storage: &Storage<V>) -> Result<JoinHandle<()>, ManifestError> {
+        let node = self.node(id)?;
+        if node.role == Role::Client {
+            return Err(ManifestError::ClientNode(id));
+        }
+        let transport = self.transport(id, node.address)?;
+        let now = Instant::now();
+        Ok(match node.role {
+            Role::Proposer => {
+                let proposer = self.proposer(id, now);
+                thread::spawn(move || run_proposer(proposer, transport))
+            }
+            Role::Acceptor => {
+                let acceptor = self.acceptor(id, now)?;
+                thread::spawn(move || run_acceptor(acceptor, transport))
+            }
+            Role::Learner => {
+                let learner = self.learner(id, now);
+                let storage = storage.clone();
+                thread::spawn(move || run_learner(learner, transport, storage))
+            }
+            Role::Client => unreachable!("clients are rejected above"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = r#"
+        storage_dir = "data"
+
+        [timeouts]
+        phase_ms = 200
+
+        [[nodes]]
+        id = 0
+        role = "proposer"
+        address = "127.0.0.1:7000"
+
+        [[nodes]]
+        id = 1
+        role = "acceptor"
+        address = "127.0.0.1:7001"
+        storage_dir = "/var/lib/paxos"
+
+        [[nodes]]
+        id = 2
+        role = "learner"
+        address = "127.0.0.1:7002"
+
+        [[nodes]]
+        id = 3
+        role = "client"
+        address = "127.0.0.1:7003"
+    "#;
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = Manifest::parse(MANIFEST).unwrap();
+        assert_eq!(manifest.ids(Role::Acceptor), vec![1]);
+        assert_eq!(manifest.node(1).unwrap().storage_dir, Some(PathBuf::from("/var/lib/paxos")));
+        assert_eq!(manifest.node(2).unwrap().address, SocketAddr::from(([127, 0, 0, 1], 7002)));
+        assert_eq!(manifest.retry().timeout, Duration::from_millis(200));
+        assert_eq!(manifest.heartbeats(), HeartbeatConfig::default());
+        assert_eq!(manifest.client_id().unwrap(), 4);
+        assert!(matches!(manifest.node(4), Err(ManifestError::UnknownNode(4))));
+        assert!(matches!(manifest.spawn::<String>(3, &Storage::default()), Err(ManifestError::ClientNode(3))));
+
+        let duplicate = MANIFEST.replace("id = 2", "id = 1");
+        assert!(matches!(Manifest::parse(&duplicate), Err(ManifestError::DuplicateId(1))));
+        let no_learner = MANIFEST.replace("\"learner\"", "\"acceptor\"");
+        assert!(matches!(Manifest::parse(&no_learner), Err(ManifestError::MissingRole(Role::Learner))));
+        let bad_role = MANIFEST.replace("\"learner\"", "\"observer\"");
+        assert!(matches!(Manifest::parse(&bad_role), Err(ManifestError::Parse(_))));
+        let zero_phase = MANIFEST.replace("phase_ms = 200", "phase_ms = 0");
+        assert!(matches!(Manifest::parse(&zero_phase), Err(ManifestError::ZeroTimeout("phase_ms"))));
+        let zero_heartbeat = MANIFEST.replace("phase_ms = 200", "heartbeat_interval_ms = 0");
+        assert!(matches!(Manifest::parse(&zero_heartbeat), Err(ManifestError::ZeroTimeout("heartbeat_interval_ms"))));
+        let max_id = Manifest::parse(&MANIFEST.replace("id = 3", &format!("id = {}", u64::MAX))).unwrap();
+        assert!(matches!(max_id.client_id(), Err(ManifestError::NoClientId)));
+    }
+}
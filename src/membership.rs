@@ -0,0 +1,236 @@
+//! Changing the set of acceptors and learners at runtime.
+//!
+//! A new `Configuration` is proposed like any other value, wrapped in
+//! `Command::Reconfigure`, and governs every slot from `alpha` slots after the
+//! one it is decided in. Nodes may only act on slots whose configuration they
+//! know, so a proposer that knows the first `n` slots are decided proposes in
+//! slots below `n + alpha` only, and the switch happens at the same slot
+//! everywhere without stopping the cluster.
+use std::collections::{BTreeMap, BTreeSet};
+use crate::codec::{CodecError, Decoder, Encoder};
+use crate::quorum::{Phase, QuorumSystem, Quorums};
+use crate::transport::NodeId;
+use crate::value::Value;
+
+/// The acceptors whose votes decide a slot and the learners they report to.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Configuration {
+    pub acceptors: Vec<NodeId>,
+    pub learners: Vec<NodeId>,
+}
+
+impl Configuration {
+    pub fn new(acceptors: Vec<NodeId>, learners: Vec<NodeId>) -> Self {
+        Configuration { acceptors, learners }
+    }
+}
+
+/// The configuration of every slot, as far as this node knows.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Membership {
+    /// Slots between deciding a configuration and using it. `None` never
+    /// changes the initial configuration.
+    alpha: Option<u64>,
+    /// Configurations by the first slot they govern.
+    configs: BTreeMap<u64, Configuration>,
+    /// Every slot below this one is decided, and any configuration decided
+    /// in them is in `configs`.
+    known: u64,
+    /// Quorums among each configuration's acceptors.
+    quorums: Quorums,
+}
+
+impl Membership {
+    /// A membership that stays at `initial`.
+    pub fn new(initial: Configuration) -> Self {
+        Membership { alpha: None, configs: BTreeMap::from([(0, initial)]), known: 0, quorums: Quorums::default() }
+    }
+
+    /// Lets decided configurations take over `alpha` slots later. Every node
+    /// must use the same `alpha`.
+    pub fn with_alpha(mut self, alpha: u64) -> Self {
+        self.alpha = Some(alpha.max(1));
+        self
+    }
+
+    /// Forms quorums with `system` instead of majorities. Every node must
+    /// use the same one.
+    pub fn with_quorum_system(mut self, system: impl QuorumSystem + 'static) -> Self {
+        self.quorums = Quorums::new(system);
+        self
+    }
+
+    pub fn alpha(&self) -> Option<u64> {
+        self.alpha
+    }
+
+    /// Number of leading slots known to be decided.
+    pub fn known(&self) -> u64 {
+        self.known
+    }
+
+    /// Configuration governing `slot`. Only final for slots below
+    /// `window_end`.
+    pub fn config(&self, slot: u64) -> &Configuration {
+        self.configs.range(..=slot).next_back().map(|(_, config)| config).unwrap()
+    }
+
+    /// First slot whose configuration is not yet known.
+    pub fn window_end(&self) -> u64 {
+        self.alpha.map_or(u64::MAX, |alpha| self.known.saturating_add(alpha))
+    }
+
+    /// Whether `responders` include a `phase` quorum of `slot`'s acceptors.
+    pub fn is_quorum(&self, slot: u64, phase: Phase, responders: &BTreeSet<NodeId>) -> bool {
+        self.quorums.is_quorum(phase, &self.config(slot).acceptors, responders)
+    }
+
+    /// Whether `responders` include a `phase` quorum of every configuration
+    /// known from `start` on.
+    pub fn is_quorum_from(&self, start: u64, phase: Phase, responders: &BTreeSet<NodeId>) -> bool {
+        self.configs(start, self.window_end()).all(|config| self.quorums.is_quorum(phase, &config.acceptors, responders))
+    }
+
+    /// Whether `rejecters` leave too few of `slot`'s acceptors for a `phase`
+    /// quorum.
+    pub fn is_blocked(&self, slot: u64, phase: Phase, rejecters: &BTreeSet<NodeId>) -> bool {
+        self.quorums.is_blocked(phase, &self.config(slot).acceptors, rejecters)
+    }
+
+    /// Configurations governing slots in `start..end`.
+    pub fn configs(&self, start: u64, end: u64) -> impl Iterator<Item = &Configuration> {
+        let current = self.config(start);
+        let later = self.configs.range(start.saturating_add(1)..end.max(start + 1)).map(|(_, config)| config);
+        std::iter::once(current).chain(later)
+    }
+
+    /// Every acceptor in a configuration governing slots from `start` on.
+    pub fn acceptors(&self, start: u64) -> Vec<NodeId> {
+        self.nodes(start, |config| &config.acceptors)
+    }
+
+    /// Every learner in a configuration governing slots from `start` on.
+    pub fn learners(&self, start: u64) -> Vec<NodeId> {
+        self.nodes(start, |config| &config.learners)
+    }
+
+    fn nodes(&self, start: u64, role: impl Fn(&Configuration) -> &Vec<NodeId>) -> Vec<NodeId> {
+        let mut nodes: Vec<NodeId> = self.configs(start, u64::MAX).flat_map(|config| role(config).clone()).collect();
+        nodes.sort();
+        nodes.dedup();
+        nodes
+    }
+
+    /// Every configuration change so far, by the first slot it governs.
+    pub fn changes(&self) -> Vec<(u64, Configuration)> {
+        self.configs.iter().map(|(slot, config)| (*slot, config.clone())).collect()
+    }
+
+    /// Notes that every slot below `known` is decided and that the ones
+    /// holding a configuration have been passed to `reconfigure`.
+    pub fn advance(&mut self, known: u64) {
+        self.known = self.known.max(known);
+    }
+
+    /// Handles `config` being decided in `slot`. A change decided while an
+    /// earlier one is still waiting to take over is ignored, so at most one
+    /// is pending at a time. Returns whether `config` will take over.
+    pub fn reconfigure(&mut self, slot: u64, config: Configuration) -> bool {
+        let Some(alpha) = self.alpha else { return false };
+        let start = slot.saturating_add(alpha);
+        if self.configs.range(slot.saturating_add(1)..start).next().is_some() {
+            return false;
+        }
+        self.configs.insert(start, config);
+        true
+    }
+
+    /// Adopts what another node knows: every slot below `known` is decided
+    /// and `changes` are all the configurations decided in them.
+    pub fn merge(&mut self, known: u64, changes: Vec<(u64, Configuration)>) {
+        self.configs.extend(changes);
+        self.advance(known);
+    }
+}
+
+const APPLY: u8 = 0;
+const RECONFIGURE: u8 = 1;
+
+/// A value of a cluster whose membership can change: either an ordinary
+/// value or a new configuration.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Command<V = String> {
+    Apply(V),
+    Reconfigure(Configuration),
+}
+
+impl<V: Value> Value for Command<V> {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        let mut encoder = Encoder { bytes: vec![] };
+        match self {
+            Command::Apply(value) => {
+                encoder.u8(APPLY);
+                value.encode(&mut encoder.bytes);
+            }
+            Command::Reconfigure(config) => {
+                encoder.u8(RECONFIGURE);
+                encoder.configuration(config);
+            }
+        }
+        bytes.extend_from_slice(&encoder.bytes);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, CodecError> {
+        let mut decoder = Decoder { bytes };
+        match decoder.u8()? {
+            APPLY => Ok(Command::Apply(V::decode(decoder.bytes)?)),
+            RECONFIGURE => {
+                let config = decoder.configuration()?;
+                if !decoder.bytes.is_empty() {
+                    return Err(CodecError::TrailingBytes);
+                }
+                Ok(Command::Reconfigure(config))
+            }
+            tag => Err(CodecError::UnknownTag(tag)),
+        }
+    }
+
+    fn reconfiguration(&self) -> Option<&Configuration> {
+        match self {
+            Command::Reconfigure(config) => Some(config),
+            Command::Apply(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_configuration_takes_over_after_alpha_slots() {
+        let old = Configuration::new(vec![1, 2, 3], vec![4]);
+        let new = Configuration::new(vec![2, 3, 5], vec![4, 6]);
+        let mut membership = Membership::new(old.clone()).with_alpha(3);
+
+        assert!(membership.reconfigure(2, new.clone()));
+        assert!(!membership.reconfigure(4, old.clone()));
+        membership.advance(3);
+        assert_eq!(membership.window_end(), 6);
+        assert_eq!(membership.config(4), &old);
+        assert_eq!(membership.config(5), &new);
+        assert_eq!(membership.acceptors(4), vec![1, 2, 3, 5]);
+        assert_eq!(membership.configs(0, 5).count(), 1);
+        assert_eq!(membership.configs(0, 6).count(), 2);
+        assert!(!Membership::new(old.clone()).reconfigure(2, new.clone()));
+
+        let mut other = Membership::new(old).with_alpha(3);
+        other.merge(membership.known(), membership.changes());
+        assert_eq!(other, membership);
+
+        let command: Command = Command::Reconfigure(new);
+        let mut bytes = vec![];
+        command.encode(&mut bytes);
+        assert_eq!(Command::decode(&bytes), Ok(command));
+    }
+}
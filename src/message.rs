@@ -1,26 +1,107 @@
 use std::fmt;
-#[derive(Debug, Clone)]
-pub enum Message {
-    Consensus(u64, String),
-    Prepare(u64, u64, String),
-    Promise(u64, u64, Option<u64>, String),
-    Propose(u64, u64, String),
-    Accept(u64, u64, String),
-    RoundNumber(u64),
-    Fail(String),
+use crate::acceptor::SlotState;
+use crate::ballot::Ballot;
+use crate::membership::Configuration;
+use crate::value::Value;
+
+/// Messages exchanged between clients, proposers, acceptors and learners.
+///
+/// Every Paxos message carries the ballot followed by the log slot it refers
+/// to, and values are of type `V`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Message<V = String> {
+    Consensus(u64, V),
+    Prepare(Ballot, u64, V),
+    /// Reply to `Prepare`: the ballot and slot promised, the value accepted
+    /// there and its ballot if any, and the acceptor's id.
+    Promise(Ballot, u64, Option<Ballot>, V, u64),
+    /// Phase 1 for every slot from the given one onward, sent by a proposer
+    /// trying to become leader.
+    PrepareAll(Ballot, u64),
+    /// Reply to `PrepareAll` listing every `(slot, ballot, value)` the
+    /// acceptor has accepted from that slot onward, and the acceptor's id.
+    PromiseAll(Ballot, u64, Vec<(u64, Ballot, V)>, u64),
+    Propose(Ballot, u64, V),
+    /// Reply to `Propose` once the value is accepted, carrying the
+    /// acceptor's id.
+    Accept(Ballot, u64, V, u64),
+    /// An acceptor's vote, sent to learners: the accepted ballot, slot and
+    /// value, and the acceptor's id.
+    Accepted(Ballot, u64, V, u64),
+    /// Sent by learners to proposers once a slot has been decided.
+    Decided(u64),
+    /// Rejection of a ballot for a slot, carrying the higher ballot the
+    /// acceptor has promised and the acceptor's id.
+    Nack(Ballot, u64, Ballot, u64),
+    /// Sign of life from the given node, with the ballot it leads with if it
+    /// is a leader.
+    Heartbeat(u64, Option<Ballot>),
+    /// Request from the given learner for what is known about slots in
+    /// `start..end`. Learners reply with `CatchUpReply`, acceptors with an
+    /// `Accepted` vote for every value they hold.
+    CatchUp(u64, u64, u64),
+    /// Decided `(slot, value)` entries sent back to a lagging learner.
+    CatchUpReply(Vec<(u64, V)>),
+    /// Part of the snapshot covering every slot below the given one, sent
+    /// to a learner asking for slots that have been compacted away: the
+    /// bytes at the given offset of a snapshot of the given length. The
+    /// parts are sent in order, each small enough for a frame.
+    Snapshot(u64, u64, u64, Vec<u8>),
+    /// Sent by learners to acceptors once every slot below the given one is
+    /// in a snapshot, so acceptors can forget them, and by acceptors to a
+    /// proposer that asks for one of those slots.
+    Compact(u64),
+    /// Sent by learners: every slot below the given one is decided, and
+    /// these are the configurations decided in them, by the first slot each
+    /// governs.
+    Configure(u64, Vec<(u64, Configuration)>),
+    /// Request from the given client for an acceptor's state, answered with
+    /// `InspectReply`.
+    Inspect(u64),
+    /// An acceptor's id, the ballot it promised for every slot, the slot its
+    /// state was truncated at, and the state of every slot it holds.
+    InspectReply(u64, Ballot, u64, Vec<(u64, SlotState<V>)>),
+    /// Like `Consensus`, from the given client, which wants `Committed`
+    /// with the request id once the value is chosen.
+    Submit(u64, u64, V),
+    /// Sent by a proposer to the client of a `Submit`: the request id and
+    /// the slot its value was chosen in.
+    Committed(u64, u64),
     Terminate,
 }
 
-impl fmt::Display for Message {
+impl<V: Value> fmt::Display for Message<V> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let msg = match self {
-            Message::Consensus(id, value) => format!("Consensus({id}, {value})"),
-            Message::Prepare(id, round_number, value) => format!("Prepare({}, {}, {})", id, round_number, value),
-            Message::Promise(id, round_number, accepted_value, value) => format!("Promise({}, {}, {:?}, {})", id, round_number, accepted_value, value),
-            Message::Propose(id, round_number, value) => format!("Propose({}, {}, {})", id, round_number, value),
-            Message::Accept(id, round_number, value) => format!("Accept({}, {}, {})", id, round_number, value),
-            Message::RoundNumber(round_number) => format!("RoundNumber({round_number})"),
-            Message::Fail(value) => format!("Fail({value})"),
+            Message::Consensus(id, value) => format!("Consensus({}, {:?})", id, value),
+            Message::Prepare(ballot, slot, value) => format!("Prepare({}, {}, {:?})", ballot, slot, value),
+            Message::Promise(ballot, slot, accepted_ballot, value, acceptor_id) => {
+                format!("Promise({}, {}, {:?}, {:?}, {})", ballot, slot, accepted_ballot, value, acceptor_id)
+            }
+            Message::PrepareAll(ballot, from) => format!("PrepareAll({}, {})", ballot, from),
+            Message::PromiseAll(ballot, from, accepted, acceptor_id) => format!("PromiseAll({}, {}, {:?}, {})", ballot, from, accepted, acceptor_id),
+            Message::Propose(ballot, slot, value) => format!("Propose({}, {}, {:?})", ballot, slot, value),
+            Message::Accept(ballot, slot, value, acceptor_id) => format!("Accept({}, {}, {:?}, {})", ballot, slot, value, acceptor_id),
+            Message::Accepted(ballot, slot, value, acceptor_id) => format!("Accepted({}, {}, {:?}, {})", ballot, slot, value, acceptor_id),
+            Message::Decided(slot) => format!("Decided({slot})"),
+            Message::Nack(ballot, slot, max_id, acceptor_id) => format!("Nack({}, {}, {}, {})", ballot, slot, max_id, acceptor_id),
+            Message::Heartbeat(from, leader) => match leader {
+                Some(ballot) => format!("Heartbeat({}, leader {})", from, ballot),
+                None => format!("Heartbeat({})", from),
+            },
+            Message::CatchUp(from, start, end) => format!("CatchUp({}, {}..{})", from, start, end),
+            Message::CatchUpReply(entries) => format!("CatchUpReply({:?})", entries),
+            Message::Snapshot(index, offset, len, part) => {
+                format!("Snapshot({}, bytes {}..{} of {})", index, offset, offset + part.len() as u64, len)
+            }
+            Message::Compact(index) => format!("Compact({index})"),
+            Message::Configure(known, changes) => format!("Configure({}, {:?})", known, changes),
+            Message::Inspect(from) => format!("Inspect({from})"),
+            Message::InspectReply(acceptor_id, promised, truncated, slots) => {
+                format!("InspectReply({}, {}, {}, {:?})", acceptor_id, promised, truncated, slots)
+            }
+            Message::Submit(from, id, value) => format!("Submit({}, {}, {:?})", from, id, value),
+            Message::Committed(id, slot) => format!("Committed({}, {})", id, slot),
             Message::Terminate => "Terminate".to_string(),
         };
         write!(f, "{msg}")
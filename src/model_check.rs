@@ -0,0 +1,557 @@
+//! Exhaustive model checking of the proposer and acceptor logic.
+//!
+//! `ModelChecker` runs the real `Proposer` and `Acceptor` handlers on a small
+//! configuration and explores every order in which in-flight messages can be
+//! delivered, within budgets for lost and duplicated messages and proposer
+//! timeouts that are shared by the whole execution. After every step it checks
+//! agreement (at most one value is chosen per slot) and validity (only
+//! submitted values are chosen). Bounding reorders instead delivers messages
+//! in the order they were sent except a given number of times, which keeps
+//! larger configurations tractable. A value counts as chosen once a phase-2
+//! quorum of acceptors, as formed by the configured quorum system, has
+//! accepted it in the same ballot.
+//!
+//! Visited states are remembered by a 64-bit fingerprint, as in TLC, so a
+//! fingerprint collision could hide part of the state space. States that
+//! differ only by a renaming of acceptors share a fingerprint, as long as the
+//! renaming maps quorums onto quorums; under majorities every renaming does.
+//!
+//! The tests explore every delivery order for two proposers, two acceptors
+//! and two values, and for two proposers and three acceptors with at most
+//! one reorder, one lost message and one timeout. Every delivery order for
+//! two proposers, three acceptors and two values without faults is about
+//! 1.8 million states and takes minutes even in release, so it is left to
+//! `cargo run --release --example model_check -- 2 3`.
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::cell::RefCell;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::time::Instant;
+use crate::acceptor::{Acceptor, AcceptorState, SlotState};
+use crate::ballot::Ballot;
+use crate::formatting::muted;
+use crate::message::Message;
+use crate::node::{handle_acceptor, handle_proposer};
+use crate::proposer::{Proposer, RetryConfig};
+use crate::quorum::{Phase, QuorumSystem, Quorums};
+use crate::transport::{NodeId, Transport};
+use crate::wal::AcceptorStorage;
+
+/// Configuration to explore. Node ids follow the same layout as `Cluster`;
+/// there are no learners, since decisions are read off the acceptors.
+pub struct ModelChecker {
+    proposers: usize,
+    acceptors: usize,
+    values: Vec<String>,
+    drops: u32,
+    duplicates: u32,
+    timeouts: u32,
+    reorders: Option<u32>,
+    quorums: Quorums,
+}
+
+impl Default for ModelChecker {
+    fn default() -> Self {
+        ModelChecker {
+            proposers: 2,
+            acceptors: 3,
+            values: vec!["a".to_string(), "b".to_string()],
+            drops: 0,
+            duplicates: 0,
+            timeouts: 0,
+            reorders: None,
+            quorums: Quorums::default(),
+        }
+    }
+}
+
+/// An invariant that failed, with the steps that led there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub invariant: String,
+    pub trace: Vec<String>,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "invariant violated: {}", self.invariant)?;
+        for (i, step) in self.trace.iter().enumerate() {
+            writeln!(f, "{:>4}. {}", i + 1, step)?;
+        }
+        Ok(())
+    }
+}
+
+impl ModelChecker {
+    pub fn new() -> Self {
+        ModelChecker::default()
+    }
+
+    pub fn proposers(mut self, proposers: usize) -> Self {
+        self.proposers = proposers;
+        self
+    }
+
+    pub fn acceptors(mut self, acceptors: usize) -> Self {
+        self.acceptors = acceptors;
+        self
+    }
+
+    /// Values submitted by clients, handed to the proposers round-robin.
+    pub fn values(mut self, values: &[&str]) -> Self {
+        self.values = values.iter().map(|value| value.to_string()).collect();
+        self
+    }
+
+    /// Number of messages that may be lost in one execution.
+    pub fn drops(mut self, drops: u32) -> Self {
+        self.drops = drops;
+        self
+    }
+
+    /// Number of messages that may be delivered twice in one execution.
+    pub fn duplicates(mut self, duplicates: u32) -> Self {
+        self.duplicates = duplicates;
+        self
+    }
+
+    /// Number of times proposer timers may fire in one execution. Both a
+    /// timeout and the retry after its backoff use one.
+    pub fn timeouts(mut self, timeouts: u32) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Number of times a message may be delivered ahead of one sent before
+    /// it in one execution. Unbounded by default, when every order is
+    /// explored.
+    pub fn reorders(mut self, reorders: u32) -> Self {
+        self.reorders = Some(reorders);
+        self
+    }
+
+    /// Forms quorums with `system` instead of majorities, both in the
+    /// proposers and when deciding which values are chosen.
+    pub fn quorum_system(mut self, system: impl QuorumSystem + 'static) -> Self {
+        self.quorums = Quorums::new(system);
+        self
+    }
+
+    /// Explores every reachable state. Returns the number of distinct states
+    /// visited, or the first invariant violation found.
+    pub fn run(&self) -> Result<usize, Violation> {
+        let acceptor_ids: Vec<NodeId> = (0..self.acceptors as u64).map(|i| i + self.proposers as u64).collect();
+        // Timers fire only when the checker says so; the timeout budget
+        // bounds retries instead.
+        let retry = RetryConfig { max_retries: u32::MAX, ..RetryConfig::default() };
+        let proposers = (0..self.proposers as u64)
+            .map(|id| {
+                Proposer::new(id, acceptor_ids.clone())
+                    .with_retry_config(retry.clone())
+                    .with_quorum_system(self.quorums.clone())
+            })
+            .collect();
+        let network = self
+            .values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| Envelope { to: (i % self.proposers) as NodeId, message: Message::Consensus(0, value.clone()) })
+            .collect();
+        let initial = State {
+            now: Instant::now(),
+            proposers,
+            acceptors: vec![AcceptorState::default(); self.acceptors],
+            network,
+            decided: BTreeMap::new(),
+            drops: self.drops,
+            duplicates: self.duplicates,
+            timeouts: self.timeouts,
+            reorders: self.reorders,
+        };
+        let renamings = permutations(self.acceptors)
+            .into_iter()
+            .filter(|renaming| preserves_quorums(&self.quorums, &acceptor_ids, renaming))
+            .collect();
+        let mut search = Search {
+            checker: self,
+            acceptor_ids,
+            renamings,
+            visited: HashSet::new(),
+            trace: vec![],
+        };
+        muted(|| search.explore(initial))?;
+        Ok(search.visited.len())
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct Envelope {
+    to: NodeId,
+    message: Message,
+}
+
+#[derive(Clone)]
+struct State {
+    now: Instant,
+    proposers: Vec<Proposer>,
+    acceptors: Vec<AcceptorState>,
+    /// In-flight messages, in the order they were sent while reorders are
+    /// bounded and kept sorted otherwise, so equal multisets compare equal.
+    network: Vec<Envelope>,
+    /// Every value decided for each slot so far.
+    decided: BTreeMap<u64, BTreeSet<String>>,
+    /// Remaining budgets.
+    drops: u32,
+    duplicates: u32,
+    timeouts: u32,
+    reorders: Option<u32>,
+}
+
+/// Hands an acceptor its state back as if it had restarted from disk.
+struct Restored(AcceptorState);
+
+impl AcceptorStorage for Restored {
+    fn persist(&mut self, _slot: u64, _state: &SlotState) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn persist_promise(&mut self, _ballot: Ballot) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn compact(&mut self, _state: &AcceptorState) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn load(&mut self) -> io::Result<AcceptorState> {
+        Ok(std::mem::take(&mut self.0))
+    }
+}
+
+/// Collects what a node sends while handling one step.
+struct Outbox {
+    id: NodeId,
+    sent: RefCell<Vec<Envelope>>,
+}
+
+impl Outbox {
+    fn new(id: NodeId) -> Self {
+        Outbox { id, sent: RefCell::new(vec![]) }
+    }
+}
+
+impl Transport for Outbox {
+    fn id(&self) -> NodeId {
+        self.id
+    }
+
+    fn send(&self, to: NodeId, message: Message) -> io::Result<()> {
+        self.sent.borrow_mut().push(Envelope { to, message });
+        Ok(())
+    }
+
+    fn recv_deadline(&self, _deadline: Option<Instant>) -> io::Result<Option<Message>> {
+        Ok(None)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Action {
+    Deliver(usize),
+    Duplicate(usize),
+    Drop(usize),
+    Timeout(usize),
+}
+
+/// Every ordering of `0..n`.
+fn permutations(n: usize) -> Vec<Vec<usize>> {
+    if n == 0 {
+        return vec![vec![]];
+    }
+    let mut all = vec![];
+    for rest in permutations(n - 1) {
+        for i in 0..n {
+            let mut permutation = rest.clone();
+            permutation.insert(i, n - 1);
+            all.push(permutation);
+        }
+    }
+    all
+}
+
+/// Whether renaming acceptor `i` to `renaming[i]` turns every quorum of
+/// `quorums` into a quorum and every other set into a non-quorum, so that
+/// renamed states behave alike.
+fn preserves_quorums(quorums: &Quorums, acceptors: &[NodeId], renaming: &[usize]) -> bool {
+    let subset = |mask: u32, rename: bool| -> BTreeSet<NodeId> {
+        (0..acceptors.len())
+            .filter(|i| mask & (1 << i) != 0)
+            .map(|i| acceptors[if rename { renaming[i] } else { i }])
+            .collect()
+    };
+    (0..1u32 << acceptors.len()).all(|mask| {
+        [Phase::One, Phase::Two].into_iter().all(|phase| {
+            quorums.is_quorum(phase, acceptors, &subset(mask, false)) == quorums.is_quorum(phase, acceptors, &subset(mask, true))
+        })
+    })
+}
+
+struct Search<'a> {
+    checker: &'a ModelChecker,
+    acceptor_ids: Vec<NodeId>,
+    /// Ways to rename the acceptors; `renaming[i]` is the new index of acceptor `i`.
+    renamings: Vec<Vec<usize>>,
+    visited: HashSet<u64>,
+    trace: Vec<String>,
+}
+
+impl Search<'_> {
+    fn explore(&mut self, mut state: State) -> Result<(), Violation> {
+        if state.reorders.is_none() {
+            state.network.sort();
+        }
+        if !self.visited.insert(self.fingerprint(&state)) {
+            return Ok(());
+        }
+        let mut actions = vec![];
+        let may_reorder = state.reorders.is_none_or(|reorders| reorders > 0);
+        for (i, envelope) in state.network.iter().enumerate() {
+            // Identical messages lead to identical states.
+            if state.reorders.is_none() && i > 0 && state.network[i - 1] == *envelope {
+                continue;
+            }
+            if i == 0 || may_reorder {
+                actions.push(Action::Deliver(i));
+                if state.duplicates > 0 {
+                    actions.push(Action::Duplicate(i));
+                }
+            }
+            if state.drops > 0 {
+                actions.push(Action::Drop(i));
+            }
+        }
+        if state.timeouts > 0 {
+            for (i, proposer) in state.proposers.iter().enumerate() {
+                if proposer.next_deadline().is_some() {
+                    actions.push(Action::Timeout(i));
+                }
+            }
+        }
+        for action in actions {
+            let mut next = state.clone();
+            let step = self.apply(&mut next, action);
+            self.trace.push(step);
+            if let Err(invariant) = self.check(&mut next) {
+                return Err(Violation { invariant, trace: self.trace.clone() });
+            }
+            self.explore(next)?;
+            self.trace.pop();
+        }
+        Ok(())
+    }
+
+    /// The smallest hash of `state` under any renaming of the acceptors.
+    /// Only renamings that order the acceptors by `signatures` can give the
+    /// smallest hash among those orders, so only they are tried when there
+    /// are any. Only the parts that mention acceptors are renamed; the rest
+    /// is hashed once.
+    fn fingerprint(&self, state: &State) -> u64 {
+        let proposers = self.checker.proposers as NodeId;
+        let rename = |renaming: &[usize], id: NodeId| match id.checked_sub(proposers) {
+            Some(i) if (i as usize) < renaming.len() => proposers + renaming[i as usize] as NodeId,
+            _ => id,
+        };
+        let signatures = self.signatures(state);
+        let sorting = |renaming: &&Vec<usize>| {
+            (0..renaming.len()).all(|i| (0..renaming.len()).all(|j| renaming[i] >= renaming[j] || signatures[i] <= signatures[j]))
+        };
+        let mut renamings: Vec<&Vec<usize>> = self.renamings.iter().filter(sorting).collect();
+        if renamings.is_empty() {
+            renamings = self.renamings.iter().collect();
+        }
+        let mut common = DefaultHasher::new();
+        (state.now, &state.decided, state.drops, state.duplicates, state.timeouts, state.reorders).hash(&mut common);
+        renamings
+            .into_iter()
+            .map(|renaming| {
+                let mut hasher = common.clone();
+                for renamed in 0..renaming.len() {
+                    let original = renaming.iter().position(|&i| i == renamed).unwrap();
+                    state.acceptors[original].hash(&mut hasher);
+                }
+                for proposer in &state.proposers {
+                    let mut proposer = proposer.clone();
+                    proposer.rename_acceptors(|id| rename(renaming, id));
+                    proposer.hash(&mut hasher);
+                }
+                let mut network = state.network.clone();
+                for envelope in &mut network {
+                    envelope.to = rename(renaming, envelope.to);
+                    match &mut envelope.message {
+                        Message::Promise(.., acceptor_id)
+                        | Message::PromiseAll(.., acceptor_id)
+                        | Message::Accept(.., acceptor_id)
+                        | Message::Nack(.., acceptor_id) => *acceptor_id = rename(renaming, *acceptor_id),
+                        _ => {}
+                    }
+                }
+                if state.reorders.is_none() {
+                    network.sort();
+                }
+                network.hash(&mut hasher);
+                hasher.finish()
+            })
+            .min()
+            .unwrap()
+    }
+
+    /// A hash of what each acceptor holds and of the messages to and from
+    /// it, with its own id left out, so that renaming the acceptors permutes
+    /// the signatures and changes nothing else.
+    fn signatures(&self, state: &State) -> Vec<u64> {
+        let mut messages: Vec<Vec<u64>> = vec![vec![]; self.acceptor_ids.len()];
+        for envelope in &state.network {
+            let mut message = envelope.message.clone();
+            let from = match &mut message {
+                Message::Promise(.., acceptor_id)
+                | Message::PromiseAll(.., acceptor_id)
+                | Message::Accept(.., acceptor_id)
+                | Message::Nack(.., acceptor_id) => Some(std::mem::take(acceptor_id)),
+                _ => None,
+            };
+            let mut hasher = DefaultHasher::new();
+            (from.is_some(), &message).hash(&mut hasher);
+            for id in from.into_iter().chain([envelope.to]) {
+                if let Some(i) = self.acceptor_ids.iter().position(|acceptor| *acceptor == id) {
+                    messages[i].push(hasher.clone().finish());
+                }
+            }
+        }
+        state
+            .acceptors
+            .iter()
+            .zip(messages)
+            .map(|(acceptor, mut messages)| {
+                messages.sort_unstable();
+                let mut hasher = DefaultHasher::new();
+                (acceptor, messages).hash(&mut hasher);
+                hasher.finish()
+            })
+            .collect()
+    }
+
+    /// Performs `action` on `state` and describes it for the trace.
+    fn apply(&self, state: &mut State, action: Action) -> String {
+        if let (Action::Deliver(i) | Action::Duplicate(i), Some(reorders)) = (action, &mut state.reorders) {
+            if i > 0 {
+                *reorders -= 1;
+            }
+        }
+        let (envelope, step) = match action {
+            Action::Deliver(i) => (state.network.remove(i), "deliver"),
+            Action::Duplicate(i) => {
+                state.duplicates -= 1;
+                (state.network[i].clone(), "duplicate")
+            }
+            Action::Drop(i) => {
+                state.drops -= 1;
+                let envelope = state.network.remove(i);
+                return format!("drop {} to {}", envelope.message, envelope.to);
+            }
+            Action::Timeout(i) => {
+                state.timeouts -= 1;
+                let proposer = &mut state.proposers[i];
+                state.now = state.now.max(proposer.next_deadline().unwrap());
+                let outbox = Outbox::new(proposer.id());
+                proposer.tick(&outbox, state.now);
+                self.send(state, outbox);
+                return format!("timer fires on proposer {}", i);
+            }
+        };
+        let description = format!("{} {} to {}", step, envelope.message, envelope.to);
+        let outbox = Outbox::new(envelope.to);
+        let node = envelope.to as usize;
+        if node < self.checker.proposers {
+            handle_proposer(&mut state.proposers[node], envelope.message, &outbox, state.now);
+        } else {
+            let acceptor_state = &mut state.acceptors[node - self.checker.proposers];
+            let mut acceptor = Acceptor::with_storage(envelope.to, Box::new(Restored(std::mem::take(acceptor_state))))
+                .expect("restoring from memory cannot fail");
+            handle_acceptor(&mut acceptor, envelope.message, &outbox, state.now);
+            *acceptor_state = acceptor.state().clone();
+        }
+        self.send(state, outbox);
+        description
+    }
+
+    /// Queues everything in `outbox`.
+    fn send(&self, state: &mut State, outbox: Outbox) {
+        state.network.extend(outbox.sent.into_inner());
+    }
+
+    /// Records values accepted by a phase-2 quorum in the same ballot and
+    /// checks agreement and validity over everything decided so far.
+    fn check(&self, state: &mut State) -> Result<(), String> {
+        let mut accepted: BTreeMap<_, BTreeSet<NodeId>> = BTreeMap::new();
+        for (acceptor, id) in state.acceptors.iter().zip(&self.acceptor_ids) {
+            for (slot, slot_state) in &acceptor.slots {
+                if let Some((ballot, value)) = &slot_state.accepted {
+                    accepted.entry((*slot, *ballot, value)).or_default().insert(*id);
+                }
+            }
+        }
+        for ((slot, _, value), acceptors) in accepted {
+            if self.checker.quorums.is_quorum(Phase::Two, &self.acceptor_ids, &acceptors) {
+                state.decided.entry(slot).or_default().insert(value.clone());
+            }
+        }
+        for (slot, values) in &state.decided {
+            if let Some(value) = values.iter().find(|value| !self.checker.values.contains(value)) {
+                return Err(format!("validity: slot {} decided {:?}, which no client submitted", slot, value));
+            }
+            if values.len() > 1 {
+                return Err(format!("agreement: slot {} decided {:?}", slot, values));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_interleaving_agrees() {
+        let states = ModelChecker::new().acceptors(2).run().unwrap();
+        assert!(states > 100, "only {} states explored", states);
+    }
+
+    #[test]
+    fn test_lost_messages_and_retries_keep_agreement() {
+        ModelChecker::new().proposers(1).values(&["a"]).drops(1).timeouts(1).run().unwrap();
+    }
+
+    #[test]
+    fn test_competing_proposers_with_faults_keep_agreement() {
+        ModelChecker::new().reorders(1).drops(1).timeouts(1).run().unwrap();
+    }
+
+    /// Any single acceptor is a quorum, so quorums need not intersect.
+    #[derive(Debug)]
+    struct AnyOne;
+
+    impl QuorumSystem for AnyOne {
+        fn is_quorum(&self, _phase: Phase, acceptors: &[NodeId], responders: &BTreeSet<NodeId>) -> bool {
+            acceptors.iter().any(|acceptor| responders.contains(acceptor))
+        }
+    }
+
+    #[test]
+    fn test_disjoint_quorums_break_agreement() {
+        let violation = ModelChecker::new().acceptors(2).quorum_system(AnyOne).run().unwrap_err();
+        assert!(violation.invariant.starts_with("agreement"), "{}", violation);
+    }
+}
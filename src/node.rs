@@ -0,0 +1,177 @@
+use std::time::Instant;
+use crate::acceptor::Acceptor;
+use crate::formatting::{log, print_red};
+use crate::learner::{Learner, Storage};
+use crate::message::Message;
+use crate::proposer::Proposer;
+use crate::transport::Transport;
+use crate::value::Value;
+
+/// Runs `proposer` until it receives `Terminate`.
+pub fn run_proposer<V: Value>(mut proposer: Proposer<V>, transport: impl Transport<V>) {
+    loop {
+        let message = transport.recv_deadline(proposer.next_deadline()).expect("[Proposer] Transport closed");
+        if let Some(message) = message {
+            if !handle_proposer(&mut proposer, message, &transport, Instant::now()) {
+                break;
+            }
+        }
+        proposer.tick(&transport, Instant::now());
+    }
+}
+
+/// Runs `acceptor` until it receives `Terminate`.
+pub fn run_acceptor<V: Value>(mut acceptor: Acceptor<V>, transport: impl Transport<V>) {
+    loop {
+        let message = transport.recv_deadline(acceptor.next_deadline()).expect("[Acceptor] Transport closed");
+        if let Some(message) = message {
+            if !handle_acceptor(&mut acceptor, message, &transport, Instant::now()) {
+                break;
+            }
+        }
+        acceptor.tick(&transport, Instant::now());
+    }
+}
+
+/// Runs `learner` until it receives `Terminate`, appending decisions to `storage`.
+pub fn run_learner<V: Value>(mut learner: Learner<V>, transport: impl Transport<V>, storage: Storage<V>) {
+    loop {
+        log!("[Learner] Waiting for message");
+        let message = transport.recv_deadline(learner.next_deadline()).expect("[Learner] Transport closed");
+        if let Some(message) = message {
+            if !handle_learner(&mut learner, message, &storage, &transport, Instant::now()) {
+                break;
+            }
+        }
+        learner.tick(&storage, &transport, Instant::now());
+    }
+}
+
+/// Feeds one message to `proposer`. Returns `false` once it should stop.
+pub fn handle_proposer<V: Value>(proposer: &mut Proposer<V>, message: Message<V>, transport: &dyn Transport<V>, now: Instant) -> bool {
+    match message {
+        Message::Consensus(id, value) => {
+            proposer.handle_consensus(transport, Some(id), value, now);
+        }
+        Message::Submit(from, id, value) => {
+            proposer.handle_submit(transport, from, id, value, now);
+        }
+        Message::Promise(ballot, slot, accepted_ballot, value, acceptor_id) => {
+            proposer.handle_promise(acceptor_id, ballot, slot, accepted_ballot.map(|accepted| (accepted, value)), transport, now);
+        }
+        Message::PromiseAll(ballot, from, accepted, acceptor_id) => {
+            proposer.handle_promise_all(acceptor_id, ballot, from, accepted, transport, now);
+        }
+        Message::Accept(ballot, slot, value, acceptor_id) => {
+            log!("[Proposer] Received ACCEPT: {:?}", value);
+            proposer.handle_accept(acceptor_id, ballot, slot, transport);
+        }
+        Message::Decided(slot) => {
+            log!("[Proposer] Received DECIDED: {:?}", slot);
+            proposer.update_decided(slot);
+        }
+        Message::Nack(ballot, slot, max_id, acceptor_id) => {
+            log!("[Proposer] Received NACK from acceptor {} promised to {}", acceptor_id, max_id);
+            proposer.handle_nack(acceptor_id, ballot, slot, max_id, now);
+        }
+        Message::Heartbeat(from, leader) => {
+            proposer.handle_heartbeat(from, leader, now);
+        }
+        Message::Configure(known, changes) => {
+            proposer.handle_configure(known, changes, transport, now);
+        }
+        Message::Compact(index) => {
+            log!("[Proposer] Received COMPACT below slot {}", index);
+            proposer.handle_compact(index, transport, now);
+        }
+        Message::Terminate => {
+            log!("[Proposer] Received TERMINATE");
+            return false;
+        }
+        _ => {
+            print_red(&format!("[Proposer] Dropping unexpected message: {:?}", message));
+        }
+    }
+    true
+}
+
+/// Feeds one message to `acceptor`. Returns `false` once it should stop.
+pub fn handle_acceptor<V: Value>(acceptor: &mut Acceptor<V>, message: Message<V>, transport: &dyn Transport<V>, now: Instant) -> bool {
+    match message {
+        Message::Prepare(ballot, slot, value) => {
+            acceptor.handle_prepare(ballot, slot, value, transport);
+        }
+        Message::PrepareAll(ballot, from) => {
+            acceptor.handle_prepare_all(ballot, from, transport);
+        }
+        Message::Propose(ballot, slot, value) => {
+            acceptor.handle_propose(ballot, slot, value, transport);
+        }
+        Message::Nack(..) => {
+            log!("[Acceptor] Received NACK");
+        }
+        Message::Heartbeat(from, _) => {
+            acceptor.handle_heartbeat(from, now);
+        }
+        Message::CatchUp(from, start, end) => {
+            acceptor.handle_catch_up(from, start, end, transport);
+        }
+        Message::Compact(index) => {
+            acceptor.handle_compact(index);
+        }
+        Message::Configure(known, changes) => {
+            acceptor.handle_configure(known, changes);
+        }
+        Message::Inspect(from) => {
+            acceptor.handle_inspect(from, transport);
+        }
+        Message::Terminate => {
+            log!("[Acceptor] Received TERMINATE");
+            return false;
+        }
+        _ => {
+            print_red(&format!("[Acceptor] Dropping unexpected message: {:?}", message));
+        }
+    }
+    true
+}
+
+/// Feeds one message to `learner`. Returns `false` once it should stop.
+pub fn handle_learner<V: Value>(
+    learner: &mut Learner<V>,
+    message: Message<V>,
+    storage: &Storage<V>,
+    transport: &dyn Transport<V>,
+    now: Instant,
+) -> bool {
+    match message {
+        Message::Accepted(ballot, slot, value, acceptor_id) => {
+            learner.handle_accepted(ballot, slot, value, acceptor_id, storage, transport);
+        }
+        Message::Heartbeat(from, _) => {
+            learner.handle_heartbeat(from, now);
+        }
+        Message::CatchUp(from, start, end) => {
+            learner.handle_catch_up(from, start, end, storage, transport);
+        }
+        Message::CatchUpReply(entries) => {
+            for (slot, value) in entries {
+                learner.record(slot, value, storage, transport);
+            }
+        }
+        Message::Snapshot(index, offset, len, part) => {
+            learner.handle_snapshot(index, offset, len, part, storage, transport);
+        }
+        Message::Configure(known, changes) => {
+            learner.handle_configure(known, changes, storage, transport);
+        }
+        Message::Terminate => {
+            log!("[Learner] Received TERMINATE");
+            return false;
+        }
+        _ => {
+            print_red(&format!("[Learner] Dropping unexpected message: {:?}", message));
+        }
+    }
+    true
+}
@@ -1,50 +1,887 @@
-use crossbeam_channel::{bounded, Receiver, Sender};
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+use crate::ballot::Ballot;
+use crate::failure_detector::FailureDetector;
+use crate::formatting::{log, print_red};
+use crate::membership::{Configuration, Membership};
+use crate::quorum::{Phase, QuorumSystem};
 use crate::message::Message;
-use crate::formatting::print_green; 
-use std::sync::{Arc, Mutex};
-pub struct Proposer {
+use crate::rng::Rng;
+use crate::transport::{NodeId, Transport};
+use crate::value::Value;
+
+/// How long a proposer waits for each phase and how it backs off between attempts.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RetryConfig {
+    /// Time allowed for a phase to reach quorum before the attempt is abandoned.
+    pub timeout: Duration,
+    /// Backoff before the first retry; doubled on every further retry.
+    pub backoff_base: Duration,
+    /// Upper bound on the backoff between retries.
+    pub backoff_max: Duration,
+    /// Number of retries allowed per value before it is dropped.
+    pub max_retries: u32,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            timeout: Duration::from_millis(500),
+            backoff_base: Duration::from_millis(10),
+            backoff_max: Duration::from_secs(1),
+            max_retries: 10,
+        }
+    }
+}
+
+/// Where a value came from: the client's request id, which is passed on
+/// when forwarding, and the client to send `Committed` to once the value is
+/// chosen, if it asked for that.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+struct Origin {
+    id: u64,
+    reply_to: Option<NodeId>,
+}
+
+/// Progress of the proposer's ballot for a single slot.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Instance<V> {
+    ballot: Ballot,
+    value: V,
+    /// Request `value` came from; the default for a value taken over from
+    /// another proposer.
+    origin: Origin,
+    /// Acceptors that promised `ballot`.
+    promised: BTreeSet<NodeId>,
+    /// Highest-ballot value already accepted by a promising acceptor.
+    highest_accepted: Option<(Ballot, V)>,
+    /// Acceptors that accepted `value` in `ballot`.
+    accepted: BTreeSet<NodeId>,
+    /// Acceptors that rejected `ballot`.
+    nacked: BTreeSet<NodeId>,
+    proposed: bool,
+    attempts: u32,
+    /// Phase deadline while in flight; retry time while backing off.
+    deadline: Instant,
+    backing_off: bool,
+}
+
+/// A `PrepareAll` round trying to make the proposer leader for every slot
+/// from `from` onward.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Election<V> {
+    ballot: Ballot,
+    from: u64,
+    /// First slot covered by every promise so far. Acceptors that compacted
+    /// slots from `from` onward only promise from their truncation point.
+    start: u64,
+    /// Acceptors that promised.
+    promised: BTreeSet<NodeId>,
+    /// Highest-ballot value accepted in each slot by a promising acceptor.
+    accepted: BTreeMap<u64, (Ballot, V)>,
+    nacked: BTreeSet<NodeId>,
+    /// Values waiting for the election to finish before they get a slot.
+    queued: Vec<(V, Origin)>,
+    attempts: u32,
+    /// Phase deadline while in flight; retry time while backing off.
+    deadline: Instant,
+    backing_off: bool,
+}
+
+/// A Multi-Paxos proposer. The first value it receives starts an election;
+/// once a quorum promises for every remaining slot, it stays leader and sends
+/// values straight to phase 2 until an acceptor reports a higher ballot.
+/// With a failure detector, other proposers hear of the leader through its
+/// heartbeats and forward values to it until they suspect it has failed.
+///
+/// Each slot needs a quorum of the acceptors that the membership assigns to
+/// it, as formed by the membership's quorum system, and values are only
+/// proposed in slots whose configuration is known.
+///
+/// Proposers are `Clone` and `Hash` so the model checker can fork and
+/// deduplicate their states.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Proposer<V = String> {
     id: u64,
-    proposal_number: u64,
-    round_number: u64,
-}
-impl Proposer {
-    pub fn new(
-        id: u64,
-        proposal_number: u64,
-    ) -> Self {
+    ballot: Ballot,
+    membership: Membership,
+    next_slot: u64,
+    instances: BTreeMap<u64, Instance<V>>,
+    /// Ballot this proposer leads with, if it has won an election.
+    leader: Option<Ballot>,
+    /// Another proposer that announced itself leader, and its ballot.
+    leader_hint: Option<(NodeId, Ballot)>,
+    election: Option<Election<V>>,
+    /// Values waiting for slots whose configuration is known.
+    waiting: Vec<(V, Origin)>,
+    detector: Option<FailureDetector>,
+    retry: RetryConfig,
+    rng: Rng,
+}
+impl<V: Value> Proposer<V> {
+    /// A majority of `acceptors` is needed in each phase. Learners hear the
+    /// outcome from the acceptors, not from the proposer.
+    pub fn new(id: u64, acceptors: Vec<NodeId>) -> Self {
         Proposer {
             id,
-            proposal_number,
-            round_number: 0,
+            ballot: Ballot::new(0, id),
+            membership: Membership::new(Configuration::new(acceptors, vec![])),
+            next_slot: 0,
+            instances: BTreeMap::new(),
+            leader: None,
+            leader_hint: None,
+            election: None,
+            waiting: vec![],
+            detector: None,
+            retry: RetryConfig::default(),
+            rng: Rng::new(id),
+        }
+    }
+
+    /// Waits for quorums of `system` instead of majorities in each phase.
+    pub fn with_quorum_system(mut self, system: impl QuorumSystem + 'static) -> Self {
+        self.membership = self.membership.with_quorum_system(system);
+        self
+    }
+
+    /// Takes the acceptors of each slot from `membership` instead.
+    pub fn with_membership(mut self, membership: Membership) -> Self {
+        self.membership = membership;
+        self
+    }
+
+    pub fn with_retry_config(mut self, retry: RetryConfig) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Sends heartbeats through `detector` and defers to live leaders.
+    pub fn with_failure_detector(mut self, detector: FailureDetector) -> Self {
+        self.detector = Some(detector);
+        self
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn failure_detector(&self) -> Option<&FailureDetector> {
+        self.detector.as_ref()
+    }
+
+    pub fn membership(&self) -> &Membership {
+        &self.membership
+    }
+
+    /// First slot this proposer has not yet used or seen decided.
+    pub fn next_slot(&self) -> u64 {
+        self.next_slot
+    }
+
+    /// Whether this proposer currently skips phase 1 for new values.
+    pub fn is_leader(&self) -> bool {
+        self.leader.is_some()
+    }
+
+    /// Earliest time at which `tick` has work to do.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let election = self.election.as_ref().map(|election| election.deadline);
+        let heartbeat = self.detector.as_ref().map(FailureDetector::next_deadline);
+        self.instances.values().map(|instance| instance.deadline).chain(election).chain(heartbeat).min()
+    }
+
+    /// A leader proposes `value` in the next free slot right away. Anyone
+    /// else forwards it to a leader it has heard from and does not suspect,
+    /// or holds it for an election. A leader past the known configurations
+    /// holds it until it hears of more decided slots. The client's request
+    /// `id` is passed on when forwarding and never affects the ballot.
+    pub fn handle_consensus(&mut self, transport: &dyn Transport<V>, id: Option<u64>, value: V, now: Instant) {
+        self.request(transport, Origin { id: id.unwrap_or(0), reply_to: None }, value, now);
+    }
+
+    /// Like `handle_consensus`, and once `value` is chosen sends client
+    /// `from` a `Committed` with request `id` and the slot.
+    pub fn handle_submit(&mut self, transport: &dyn Transport<V>, from: NodeId, id: u64, value: V, now: Instant) {
+        self.request(transport, Origin { id, reply_to: Some(from) }, value, now);
+    }
+
+    fn request(&mut self, transport: &dyn Transport<V>, origin: Origin, value: V, now: Instant) {
+        if let Some(ballot) = self.leader {
+            if self.next_slot >= self.membership.window_end() {
+                self.waiting.push((value, origin));
+                return;
+            }
+            let slot = self.next_slot;
+            self.next_slot += 1;
+            self.propose_as_leader(ballot, slot, value, origin, transport, now);
+            return;
+        }
+        if let (Some((leader, ballot)), Some(detector)) = (self.leader_hint, &self.detector) {
+            // Only forward to a ballot newer than our own, so two former
+            // leaders never bounce a value between them.
+            if ballot > self.ballot && !detector.is_suspected(leader, now) {
+                log!("[Proposer] Forwarding {:?} to leader {}", value, leader);
+                let message = match origin.reply_to {
+                    Some(client) => Message::Submit(client, origin.id, value),
+                    None => Message::Consensus(origin.id, value),
+                };
+                transport.broadcast(&[leader], &message);
+                return;
+            }
+        }
+        if let Some(election) = &mut self.election {
+            election.queued.push((value, origin));
+            return;
+        }
+        self.election = Some(Election {
+            ballot: self.ballot,
+            from: self.next_slot,
+            start: self.next_slot,
+            promised: BTreeSet::new(),
+            accepted: BTreeMap::new(),
+            nacked: BTreeSet::new(),
+            queued: vec![(value, origin)],
+            attempts: 0,
+            deadline: now,
+            backing_off: false,
+        });
+        self.elect(transport, now);
+    }
+
+    /// Starts phase 1 for every slot not yet known to be decided, with a
+    /// ballot higher than any used so far.
+    fn elect(&mut self, transport: &dyn Transport<V>, now: Instant) {
+        self.ballot = self.ballot.next(self.id);
+        let ballot = self.ballot;
+        let from = self.instances.keys().next().map_or(self.next_slot, |slot| self.next_slot.min(*slot));
+        let election = self.election.as_mut().unwrap();
+        election.ballot = ballot;
+        election.from = from;
+        election.start = from;
+        election.promised.clear();
+        election.accepted.clear();
+        election.nacked.clear();
+        election.backing_off = false;
+        election.deadline = now + self.retry.timeout;
+        let message = Message::PrepareAll(ballot, from);
+        log!("[Proposer] Sending message: {:?}", message);
+        transport.broadcast(&self.membership.acceptors(from), &message);
+    }
+
+    /// Records a heartbeat from `from`. A heartbeat from a leader with a
+    /// higher ballot makes this proposer step down and forward to it.
+    pub fn handle_heartbeat(&mut self, from: NodeId, leader: Option<Ballot>, now: Instant) {
+        if let Some(detector) = &mut self.detector {
+            detector.heard_from(from, now);
+        }
+        let Some(ballot) = leader else { return };
+        if from == self.id || self.leader_hint.is_some_and(|(_, hint)| hint >= ballot) {
+            return;
+        }
+        self.leader_hint = Some((from, ballot));
+        if self.leader.is_some_and(|own| own < ballot) {
+            log!("[Proposer] Lost leadership to proposer {}", from);
+            self.leader = None;
+        }
+    }
+
+    /// Counts `acceptor`'s promise for every slot from `from` onward; a
+    /// quorum makes this proposer leader. A promise may start past the
+    /// election's first slot when the acceptor has compacted the slots in
+    /// between, which are decided.
+    pub fn handle_promise_all(
+        &mut self,
+        acceptor: NodeId,
+        ballot: Ballot,
+        from: u64,
+        accepted: Vec<(u64, Ballot, V)>,
+        transport: &dyn Transport<V>,
+        now: Instant,
+    ) {
+        let Some(election) = &mut self.election else { return };
+        if election.ballot != ballot || from < election.from || election.backing_off {
+            return;
+        }
+        election.promised.insert(acceptor);
+        election.start = election.start.max(from);
+        for (slot, accepted_ballot, value) in accepted {
+            if election.accepted.get(&slot).is_none_or(|(highest, _)| accepted_ballot > *highest) {
+                election.accepted.insert(slot, (accepted_ballot, value));
+            }
+        }
+        self.try_lead(transport, now);
+    }
+
+    /// Leads once the promises include a quorum of every configuration
+    /// known from the election's `start` on. Waits for more configurations
+    /// when a promising acceptor accepted a value in a slot past them.
+    fn try_lead(&mut self, transport: &dyn Transport<V>, now: Instant) {
+        let Some(election) = &self.election else { return };
+        let window_end = self.membership.window_end();
+        if election.backing_off || election.accepted.range(window_end..).next().is_some() {
+            return;
+        }
+        if self.membership.is_quorum_from(election.start, Phase::One, &election.promised) {
+            self.lead(transport, now);
+        }
+    }
+
+    /// Takes over every slot from the election's `start` onward. Slots where
+    /// a promising acceptor accepted a value are proposed again with the
+    /// highest-ballot one; queued values fill the remaining gaps first and
+    /// then new slots, and wait once slots run past the known
+    /// configurations. Values of attempts at earlier slots, which are decided
+    /// and compacted away, are queued again.
+    fn lead(&mut self, transport: &dyn Transport<V>, now: Instant) {
+        let mut election = self.election.take().unwrap();
+        let ballot = election.ballot;
+        log!("[Proposer] Elected leader with ballot {} from slot {}", ballot, election.start);
+        self.leader = Some(ballot);
+        self.ballot = self.ballot.max(ballot);
+        let mut queued = self.take_compacted(election.start);
+        queued.append(&mut election.queued);
+        self.next_slot = self.next_slot.max(election.start);
+        let mut slots: BTreeMap<u64, (V, Origin)> =
+            self.instances.iter().map(|(slot, instance)| (*slot, (instance.value.clone(), instance.origin))).collect();
+        for (slot, (_, value)) in election.accepted.split_off(&election.start) {
+            match slots.entry(slot) {
+                Entry::Occupied(mut own) => {
+                    if own.get().0 != value {
+                        queued.push(own.insert((value, Origin::default())));
+                    }
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert((value, Origin::default()));
+                }
+            }
+        }
+        let end = slots.keys().next_back().map_or(self.next_slot, |slot| self.next_slot.max(slot + 1));
+        let window_end = self.membership.window_end();
+        let gaps: Vec<u64> =
+            (election.start..end.min(window_end)).filter(|slot| !slots.contains_key(slot)).collect();
+        let mut queued = queued.into_iter();
+        for (slot, request) in gaps.into_iter().zip(&mut queued) {
+            slots.insert(slot, request);
+        }
+        self.next_slot = end;
+        for (slot, (value, origin)) in slots {
+            self.propose_as_leader(ballot, slot, value, origin, transport, now);
+        }
+        for (value, origin) in queued {
+            self.request(transport, origin, value, now);
+        }
+    }
+
+    /// Starts phase 2 for `slot` under the leader's ballot, keeping the retry
+    /// count of an earlier attempt at the slot.
+    fn propose_as_leader(&mut self, ballot: Ballot, slot: u64, value: V, origin: Origin, transport: &dyn Transport<V>, now: Instant) {
+        let attempts = self.instances.get(&slot).map_or(0, |instance| instance.attempts);
+        self.instances.insert(slot, Instance {
+            ballot,
+            value: value.clone(),
+            origin,
+            promised: BTreeSet::new(),
+            highest_accepted: None,
+            accepted: BTreeSet::new(),
+            nacked: BTreeSet::new(),
+            proposed: true,
+            attempts,
+            deadline: now + self.retry.timeout,
+            backing_off: false,
+        });
+        self.propose(ballot, slot, value, transport);
+    }
+
+    /// Starts phase 1 for `slot` with a ballot higher than any used so far.
+    fn prepare(&mut self, slot: u64, transport: &dyn Transport<V>, now: Instant) {
+        self.ballot = self.ballot.next(self.id);
+        let ballot = self.ballot;
+        let instance = self.instances.get_mut(&slot).unwrap();
+        instance.ballot = ballot;
+        instance.promised.clear();
+        instance.highest_accepted = None;
+        instance.accepted.clear();
+        instance.nacked.clear();
+        instance.proposed = false;
+        instance.backing_off = false;
+        instance.deadline = now + self.retry.timeout;
+        let message = Message::Prepare(ballot, slot, instance.value.clone());
+        log!("[Proposer] Sending message: {:?}", message);
+        transport.broadcast(&self.membership.config(slot).acceptors, &message);
+    }
+
+    /// Records that `slot` has been decided so it is never reused for a new value.
+    pub fn update_decided(&mut self, slot: u64) {
+        self.next_slot = self.next_slot.max(slot + 1);
+    }
+
+    /// An acceptor has compacted every slot below `index` away, so attempts
+    /// there can never finish. Their values are proposed again in fresh
+    /// slots.
+    pub fn handle_compact(&mut self, index: u64, transport: &dyn Transport<V>, now: Instant) {
+        self.next_slot = self.next_slot.max(index);
+        for (value, origin) in self.take_compacted(index) {
+            log!("[Proposer] Slot below {} compacted, retrying {:?}", index, value);
+            self.request(transport, origin, value, now);
+        }
+    }
+
+    /// Drops the attempts at slots below `index` and returns their values
+    /// and origins.
+    /// Those slots are decided, though not necessarily with these values. A
+    /// value chosen just before its slot was compacted cannot be told apart
+    /// and ends up decided twice; learners apply a request only once.
+    fn take_compacted(&mut self, index: u64) -> Vec<(V, Origin)> {
+        let kept = self.instances.split_off(&index);
+        std::mem::replace(&mut self.instances, kept).into_values().map(|instance| (instance.value, instance.origin)).collect()
+    }
+
+    /// Adopts what a learner knows of decided slots and configurations, and
+    /// proposes the values that were waiting for them. A new configuration's
+    /// acceptors have promised nothing yet, so a leader steps down and an
+    /// election starts over to include them.
+    pub fn handle_configure(&mut self, known: u64, changes: Vec<(u64, Configuration)>, transport: &dyn Transport<V>, now: Instant) {
+        let configs = self.membership.changes().len();
+        self.membership.merge(known, changes);
+        self.next_slot = self.next_slot.max(known);
+        if self.membership.changes().len() > configs {
+            if self.leader.take().is_some() {
+                log!("[Proposer] Stepping down for a new configuration");
+            }
+            if self.election.as_ref().is_some_and(|election| !election.backing_off) {
+                self.elect(transport, now);
+            }
+        }
+        for (value, origin) in std::mem::take(&mut self.waiting) {
+            self.request(transport, origin, value, now);
+        }
+        self.try_lead(transport, now);
+    }
+
+    /// Counts `acceptor`'s promise for `slot`, with the ballot and value it
+    /// accepted there if any. Once the promises form a phase-1 quorum,
+    /// proposes the highest-ballot value they report, or this proposer's own.
+    pub fn handle_promise(
+        &mut self,
+        acceptor: NodeId,
+        ballot: Ballot,
+        slot: u64,
+        accepted: Option<(Ballot, V)>,
+        transport: &dyn Transport<V>,
+        now: Instant,
+    ) {
+        let Some(instance) = self.instances.get_mut(&slot) else { return };
+        if instance.ballot != ballot || instance.proposed || instance.backing_off {
+            return;
+        }
+        instance.promised.insert(acceptor);
+        if let Some((accepted_ballot, value)) = accepted {
+            if instance.highest_accepted.as_ref().is_none_or(|(highest, _)| accepted_ballot > *highest) {
+                instance.highest_accepted = Some((accepted_ballot, value));
+            }
+        }
+        if !self.membership.is_quorum(slot, Phase::One, &instance.promised) {
+            return;
+        }
+        log!("[Proposer] Achieved quorum for slot {}", slot);
+        instance.proposed = true;
+        instance.deadline = now + self.retry.timeout;
+        let accepted = instance.highest_accepted.as_ref().map(|(_, value)| value.clone());
+        let own_value = instance.value.clone();
+        let propose_value = accepted.unwrap_or_else(|| own_value.clone());
+        self.propose(ballot, slot, propose_value.clone(), transport);
+        if propose_value != own_value {
+            // The slot is already bound to another value; retry ours in a fresh slot.
+            log!("[Proposer] Slot {} taken by {:?}, retrying {:?}", slot, propose_value, own_value);
+            let instance = self.instances.get_mut(&slot).unwrap();
+            instance.value = propose_value;
+            let origin = std::mem::take(&mut instance.origin);
+            self.request(transport, origin, own_value, now);
+        }
+    }
+
+    /// Counts `acceptor`'s `Accept`; once a phase-2 quorum agrees the slot
+    /// needs no more attempts, and the client that submitted the value, if
+    /// any, is told its slot.
+    pub fn handle_accept(&mut self, acceptor: NodeId, ballot: Ballot, slot: u64, transport: &dyn Transport<V>) {
+        let Some(instance) = self.instances.get_mut(&slot) else { return };
+        if instance.ballot != ballot || instance.backing_off {
+            return;
+        }
+        instance.accepted.insert(acceptor);
+        if self.membership.is_quorum(slot, Phase::Two, &instance.accepted) {
+            log!("[Proposer] ACCEPT QUORUM REACHED for slot {}", slot);
+            let origin = self.instances.remove(&slot).unwrap().origin;
+            if let Some(client) = origin.reply_to {
+                transport.broadcast(&[client], &Message::Committed(origin.id, slot));
+            }
+        }
+    }
+
+    /// Counts `acceptor`'s rejection of `ballot`. Once the acceptors left
+    /// cannot make a quorum, the attempt is abandoned.
+    ///
+    /// `max_id` is the ballot the acceptor has promised instead, so the next
+    /// attempt starts above it rather than climbing one round at a time. A
+    /// leader that sees a higher ballot steps down.
+    pub fn handle_nack(&mut self, acceptor: NodeId, ballot: Ballot, slot: u64, max_id: Ballot, now: Instant) {
+        if max_id.round > self.ballot.round {
+            self.ballot = Ballot::new(max_id.round, self.id);
+        }
+        if self.leader.is_some_and(|leader| max_id > leader) {
+            log!("[Proposer] Lost leadership to ballot {}", max_id);
+            self.leader = None;
+        }
+        if let Some(election) = &mut self.election {
+            if election.ballot == ballot {
+                if !election.backing_off {
+                    election.nacked.insert(acceptor);
+                    if self.membership.is_blocked(election.from, Phase::One, &election.nacked) {
+                        log!("[Proposer] NACK QUORUM for election from slot {}", slot);
+                        self.back_off_election(now);
+                    }
+                }
+                return;
+            }
+        }
+        let Some(instance) = self.instances.get_mut(&slot) else { return };
+        if instance.ballot != ballot || instance.backing_off {
+            return;
+        }
+        instance.nacked.insert(acceptor);
+        let phase = if instance.proposed { Phase::Two } else { Phase::One };
+        if self.membership.is_blocked(slot, phase, &instance.nacked) {
+            log!("[Proposer] NACK QUORUM for slot {}", slot);
+            self.back_off(slot, now);
         }
     }
 
-    pub fn handle_consensus(&mut self, tx: &Arc<Mutex<Vec<Sender<Message>>>>, id: Option<u64>, value: String) {
-        // self.round_number += 1;
-        let proposal_number = self.proposal_number + 1 + id.unwrap_or(0);
-        let message = Message::Prepare(proposal_number, self.round_number, value);
-        for acceptor in tx.lock().unwrap().iter() {
-            println!("[Proposer] Sending message: {:?}", message);
-            acceptor.send(message.clone()).unwrap();
+    /// Retries timed-out attempts. Once a backoff has elapsed, a leader
+    /// proposes again under its ballot; anyone else restarts phase 1.
+    pub fn tick(&mut self, transport: &dyn Transport<V>, now: Instant) {
+        if let Some(detector) = &mut self.detector {
+            detector.tick(self.leader, transport, now);
+        }
+        if let Some(election) = &self.election {
+            if election.deadline <= now {
+                if election.backing_off {
+                    self.elect(transport, now);
+                } else {
+                    log!("[Proposer] TIMEOUT for election");
+                    self.back_off_election(now);
+                }
+            }
+        }
+        let due: Vec<u64> = self
+            .instances
+            .iter()
+            .filter(|(_, instance)| instance.deadline <= now)
+            .map(|(slot, _)| *slot)
+            .collect();
+        for slot in due {
+            if self.instances[&slot].backing_off {
+                match self.leader {
+                    Some(ballot) => {
+                        let instance = &self.instances[&slot];
+                        let (value, origin) = (instance.value.clone(), instance.origin);
+                        self.propose_as_leader(ballot, slot, value, origin, transport, now);
+                    }
+                    None => self.prepare(slot, transport, now),
+                }
+            } else {
+                log!("[Proposer] TIMEOUT for slot {}", slot);
+                self.back_off(slot, now);
+            }
+        }
+    }
+
+    /// Schedules a retry of `slot` after a randomized exponential backoff, or
+    /// drops the value once the retry budget is spent.
+    fn back_off(&mut self, slot: u64, now: Instant) {
+        let attempts = self.instances[&slot].attempts;
+        if attempts >= self.retry.max_retries {
+            print_red(&format!("[Proposer] Giving up on slot {} value {:?}", slot, self.instances[&slot].value));
+            self.instances.remove(&slot);
+            return;
+        }
+        let backoff = self.backoff(attempts);
+        let instance = self.instances.get_mut(&slot).unwrap();
+        instance.attempts += 1;
+        instance.backing_off = true;
+        instance.deadline = now + backoff;
+    }
+
+    /// Like `back_off`, for the election. Giving up drops the queued values.
+    fn back_off_election(&mut self, now: Instant) {
+        let attempts = self.election.as_ref().unwrap().attempts;
+        if attempts >= self.retry.max_retries {
+            let election = self.election.take().unwrap();
+            let values: Vec<&V> = election.queued.iter().map(|(value, _)| value).collect();
+            print_red(&format!("[Proposer] Giving up on election, dropping {:?}", values));
+            return;
+        }
+        let backoff = self.backoff(attempts);
+        let election = self.election.as_mut().unwrap();
+        election.attempts += 1;
+        election.backing_off = true;
+        election.deadline = now + backoff;
+    }
+
+    /// Replaces every acceptor id this proposer has heard from with
+    /// `rename(id)`, for the model checker's symmetry reduction. The
+    /// membership is left alone, so `rename` must map its acceptors onto
+    /// each other in a way its quorum system cannot tell apart.
+    pub(crate) fn rename_acceptors(&mut self, rename: impl Fn(NodeId) -> NodeId) {
+        let renamed = |ids: &BTreeSet<NodeId>| ids.iter().map(|id| rename(*id)).collect();
+        for instance in self.instances.values_mut() {
+            instance.promised = renamed(&instance.promised);
+            instance.accepted = renamed(&instance.accepted);
+            instance.nacked = renamed(&instance.nacked);
+        }
+        if let Some(election) = &mut self.election {
+            election.promised = renamed(&election.promised);
+            election.nacked = renamed(&election.nacked);
         }
-        self.proposal_number += 1 + id.unwrap_or(0);
     }
 
-    pub fn update_round_number(&mut self, round_number: u64) {
-        self.round_number = round_number;
+    /// Randomized exponential backoff before retry number `attempts + 1`.
+    fn backoff(&mut self, attempts: u32) -> Duration {
+        let ceiling = self
+            .retry
+            .backoff_base
+            .saturating_mul(1 << attempts.min(16))
+            .min(self.retry.backoff_max);
+        ceiling.mul_f64(0.5 + self.rng.next_f64() / 2.0)
     }
 
     pub fn propose(
         &self,
-        proposal_number: u64,
-        round_number: u64,
-        value: String,
-        tx: & Arc<Mutex<Vec<Sender<Message>>>>,
+        ballot: Ballot,
+        slot: u64,
+        value: V,
+        transport: &dyn Transport<V>,
     ) {
-        let message = Message::Propose(proposal_number, round_number, value);
-        for acceptor in tx.lock().unwrap().iter() {
-            println!("[Proposer] Sending message: {:?}", message);
-            acceptor.send(message.clone()).unwrap();
+        let message = Message::Propose(ballot, slot, value);
+        log!("[Proposer] Sending message: {:?}", message);
+        transport.broadcast(&self.membership.config(slot).acceptors, &message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::tests::drain;
+    use crate::transport::ChannelTransport;
+
+    /// Makes `proposer` leader with a promise from both acceptors of a
+    /// two-acceptor network, returning the leader ballot.
+    fn elect(proposer: &mut Proposer, nodes: &[ChannelTransport], accepted: Vec<(u64, Ballot, String)>, now: Instant) -> Ballot {
+        let Message::PrepareAll(ballot, from) = drain(&nodes[1]).remove(0) else { panic!() };
+        proposer.handle_promise_all(1, ballot, from, accepted.clone(), &nodes[0], now);
+        proposer.handle_promise_all(2, ballot, from, accepted, &nodes[0], now);
+        ballot
+    }
+
+    #[test]
+    fn test_nack_quorum_retries_past_promised_ballot() {
+        let nodes: Vec<ChannelTransport> = ChannelTransport::network(&[0, 1, 2]);
+        let now = Instant::now();
+        let mut proposer = Proposer::new(0, vec![1, 2]);
+
+        proposer.handle_consensus(&nodes[0], None, "a".to_string(), now);
+        let Message::PrepareAll(first, 0) = drain(&nodes[1]).remove(0) else { panic!() };
+        let promised = Ballot::new(7, 1);
+        proposer.handle_nack(1, first, 0, promised, now);
+        proposer.handle_nack(2, first, 0, promised, now);
+
+        proposer.tick(&nodes[0], now);
+        assert!(drain(&nodes[1]).is_empty());
+
+        proposer.tick(&nodes[0], proposer.next_deadline().unwrap());
+        let second = elect(&mut proposer, &nodes, vec![], now);
+        assert_eq!(second, Ballot::new(8, 0));
+        assert!(second > promised);
+        assert_eq!(drain(&nodes[1]), vec![Message::Propose(second, 0, "a".to_string())]);
+    }
+
+    #[test]
+    fn test_request_id_does_not_change_the_ballot() {
+        let nodes: Vec<ChannelTransport> = ChannelTransport::network(&[0, 1, 2]);
+        let now = Instant::now();
+        let mut proposer = Proposer::new(0, vec![1, 2]);
+
+        proposer.handle_consensus(&nodes[0], Some(u64::MAX), "a".to_string(), now);
+        assert_eq!(drain(&nodes[1]), vec![Message::PrepareAll(Ballot::new(1, 0), 0)]);
+    }
+
+    #[test]
+    fn test_gives_up_after_retry_budget() {
+        let nodes: Vec<ChannelTransport> = ChannelTransport::network(&[0, 1, 2]);
+        let mut now = Instant::now();
+        let retry = RetryConfig { max_retries: 2, ..RetryConfig::default() };
+        let mut proposer = Proposer::new(0, vec![1, 2]).with_retry_config(retry);
+
+        proposer.handle_consensus(&nodes[0], None, "a".to_string(), now);
+        while let Some(deadline) = proposer.next_deadline() {
+            now = deadline;
+            proposer.tick(&nodes[0], now);
+        }
+        let prepares = drain(&nodes[1]).into_iter().filter(|m| matches!(m, Message::PrepareAll(..))).count();
+        assert_eq!(prepares, 3);
+    }
+
+    #[test]
+    fn test_leader_skips_phase_one_until_preempted() {
+        let nodes: Vec<ChannelTransport> = ChannelTransport::network(&[0, 1, 2]);
+        let now = Instant::now();
+        let mut proposer = Proposer::new(0, vec![1, 2]);
+
+        proposer.handle_consensus(&nodes[0], None, "a".to_string(), now);
+        proposer.handle_consensus(&nodes[0], None, "b".to_string(), now);
+        let ballot = elect(&mut proposer, &nodes, vec![], now);
+        assert!(proposer.is_leader());
+        proposer.handle_consensus(&nodes[0], None, "c".to_string(), now);
+        assert_eq!(drain(&nodes[1]), vec![
+            Message::Propose(ballot, 0, "a".to_string()),
+            Message::Propose(ballot, 1, "b".to_string()),
+            Message::Propose(ballot, 2, "c".to_string()),
+        ]);
+
+        proposer.handle_nack(1, ballot, 2, Ballot::new(9, 1), now);
+        assert!(!proposer.is_leader());
+        proposer.handle_consensus(&nodes[0], None, "d".to_string(), now);
+        assert!(matches!(drain(&nodes[1])[..], [Message::PrepareAll(ballot, 0)] if ballot > Ballot::new(9, 1)));
+    }
+
+    #[test]
+    fn test_new_leader_finishes_accepted_slots_and_fills_gaps() {
+        let nodes: Vec<ChannelTransport> = ChannelTransport::network(&[0, 1, 2]);
+        let now = Instant::now();
+        let mut proposer = Proposer::new(0, vec![1, 2]);
+
+        proposer.handle_consensus(&nodes[0], None, "a".to_string(), now);
+        proposer.handle_consensus(&nodes[0], None, "b".to_string(), now);
+        let old = Ballot::new(3, 1);
+        let ballot = elect(&mut proposer, &nodes, vec![(0, old, "x".to_string()), (2, old, "y".to_string())], now);
+
+        assert_eq!(drain(&nodes[1]), vec![
+            Message::Propose(ballot, 0, "x".to_string()),
+            Message::Propose(ballot, 1, "a".to_string()),
+            Message::Propose(ballot, 2, "y".to_string()),
+            Message::Propose(ballot, 3, "b".to_string()),
+        ]);
+        assert_eq!(proposer.next_slot(), 4);
+    }
+
+    #[test]
+    fn test_submitter_hears_the_slot_its_value_is_chosen_in() {
+        let nodes: Vec<ChannelTransport> = ChannelTransport::network(&[0, 1, 2, 3]);
+        let now = Instant::now();
+        let mut proposer = Proposer::new(0, vec![1, 2]);
+
+        proposer.handle_submit(&nodes[0], 3, 7, "a".to_string(), now);
+        let ballot = elect(&mut proposer, &nodes, vec![(0, Ballot::new(1, 1), "x".to_string())], now);
+        for acceptor in [1, 2] {
+            proposer.handle_accept(acceptor, ballot, 0, &nodes[0]);
+        }
+        assert!(drain(&nodes[3]).is_empty());
+        for acceptor in [1, 2] {
+            proposer.handle_accept(acceptor, ballot, 1, &nodes[0]);
         }
+        assert_eq!(drain(&nodes[3]), vec![Message::Committed(7, 1)]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_new_leader_skips_compacted_slots() {
+        let nodes: Vec<ChannelTransport> = ChannelTransport::network(&[0, 1, 2]);
+        let now = Instant::now();
+        let mut proposer = Proposer::new(0, vec![1, 2]);
+
+        proposer.handle_consensus(&nodes[0], None, "a".to_string(), now);
+        let Message::PrepareAll(ballot, 0) = drain(&nodes[1]).remove(0) else { panic!() };
+        let old = Ballot::new(1, 1);
+        proposer.handle_promise_all(1, ballot, 0, vec![(1, old, "x".to_string())], &nodes[0], now);
+        proposer.handle_promise_all(2, ballot, 3, vec![(4, old, "y".to_string())], &nodes[0], now);
+
+        assert_eq!(drain(&nodes[1]), vec![
+            Message::Propose(ballot, 3, "a".to_string()),
+            Message::Propose(ballot, 4, "y".to_string()),
+        ]);
+        assert_eq!(proposer.next_slot(), 5);
+    }
+
+    #[test]
+    fn test_pending_slots_move_past_compaction() {
+        let nodes: Vec<ChannelTransport> = ChannelTransport::network(&[0, 1, 2]);
+        let now = Instant::now();
+        let mut proposer = Proposer::new(0, vec![1, 2]);
+
+        proposer.handle_consensus(&nodes[0], None, "a".to_string(), now);
+        let ballot = elect(&mut proposer, &nodes, vec![], now);
+        proposer.handle_consensus(&nodes[0], None, "b".to_string(), now);
+        drain(&nodes[1]);
+        // A learner snapshots slots 0..3, decided by some other proposer, and
+        // the acceptors drop them before our proposals arrive.
+        proposer.handle_compact(3, &nodes[0], now);
+        proposer.handle_compact(3, &nodes[0], now);
+
+        assert_eq!(drain(&nodes[1]), vec![
+            Message::Propose(ballot, 3, "a".to_string()),
+            Message::Propose(ballot, 4, "b".to_string()),
+        ]);
+        assert_eq!(proposer.next_slot(), 5);
+    }
+
+    #[test]
+    fn test_duplicate_and_stale_replies_are_ignored() {
+        let nodes: Vec<ChannelTransport> = ChannelTransport::network(&[0, 1, 2, 3]);
+        let now = Instant::now();
+        let mut proposer = Proposer::new(0, vec![1, 2, 3]);
+
+        proposer.handle_consensus(&nodes[0], None, "a".to_string(), now);
+        let Message::PrepareAll(ballot, 0) = drain(&nodes[1]).remove(0) else { panic!() };
+        proposer.handle_promise_all(1, ballot, 0, vec![], &nodes[0], now);
+        proposer.handle_promise_all(1, ballot, 0, vec![], &nodes[0], now);
+        proposer.handle_promise_all(2, Ballot::new(ballot.round - 1, 0), 0, vec![], &nodes[0], now);
+        assert!(!proposer.is_leader());
+        proposer.handle_promise_all(2, ballot, 0, vec![], &nodes[0], now);
+        assert!(proposer.is_leader());
+
+        proposer.handle_accept(1, ballot, 0, &nodes[0]);
+        proposer.handle_accept(1, ballot, 0, &nodes[0]);
+        proposer.handle_accept(2, Ballot::new(ballot.round - 1, 0), 0, &nodes[0]);
+        assert!(proposer.instances.contains_key(&0));
+        proposer.handle_accept(3, ballot, 0, &nodes[0]);
+        assert!(!proposer.instances.contains_key(&0));
+
+        proposer.handle_consensus(&nodes[0], None, "b".to_string(), now);
+        proposer.handle_nack(1, ballot, 1, ballot, now);
+        proposer.handle_nack(1, ballot, 1, ballot, now);
+        assert!(!proposer.instances[&1].backing_off);
+        proposer.handle_nack(2, ballot, 1, ballot, now);
+        assert!(proposer.instances[&1].backing_off);
+    }
+
+    #[test]
+    fn test_leader_waits_for_known_configurations() {
+        let nodes: Vec<ChannelTransport> = ChannelTransport::network(&[0, 1, 2, 3]);
+        let now = Instant::now();
+        let membership = Membership::new(Configuration::new(vec![1, 2], vec![])).with_alpha(1);
+        let mut proposer = Proposer::new(0, vec![]).with_membership(membership);
+
+        proposer.handle_consensus(&nodes[0], None, "a".to_string(), now);
+        proposer.handle_consensus(&nodes[0], None, "b".to_string(), now);
+        let ballot = elect(&mut proposer, &nodes, vec![], now);
+        assert_eq!(drain(&nodes[1]), vec![Message::Propose(ballot, 0, "a".to_string())]);
+
+        proposer.handle_configure(1, vec![], &nodes[0], now);
+        assert_eq!(drain(&nodes[1]), vec![Message::Propose(ballot, 1, "b".to_string())]);
+
+        let config = Configuration::new(vec![1, 3], vec![]);
+        proposer.handle_configure(2, vec![(2, config)], &nodes[0], now);
+        assert!(!proposer.is_leader());
+        proposer.handle_consensus(&nodes[0], None, "c".to_string(), now);
+        assert!(matches!(drain(&nodes[3])[..], [Message::PrepareAll(..)]));
+    }
+}
@@ -0,0 +1,265 @@
+//! Which sets of acceptors make a quorum in each phase.
+//!
+//! Paxos only needs every phase-1 quorum to intersect every phase-2 quorum
+//! (Flexible Paxos), so the two phases may use different quorums: small
+//! phase-2 quorums make each decision cheaper at the price of larger ones for
+//! the rarer elections.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use crate::transport::NodeId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// `Prepare` and `PrepareAll`, answered by promises.
+    One,
+    /// `Propose`, answered by `Accept` to the proposer and `Accepted` to the
+    /// learners.
+    Two,
+}
+
+/// Decides whether the acceptors that answered form a quorum.
+///
+/// Every phase-1 quorum must share an acceptor with every phase-2 quorum of
+/// the same `acceptors`; implementations are trusted to guarantee it.
+pub trait QuorumSystem: fmt::Debug + Send + Sync {
+    /// Whether `responders` include a quorum of `acceptors` for `phase`.
+    /// Responders that are not among `acceptors` do not count.
+    fn is_quorum(&self, phase: Phase, acceptors: &[NodeId], responders: &BTreeSet<NodeId>) -> bool;
+}
+
+fn count(acceptors: &[NodeId], responders: &BTreeSet<NodeId>) -> usize {
+    acceptors.iter().filter(|acceptor| responders.contains(acceptor)).count()
+}
+
+/// More than half of the acceptors, in both phases.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Majority;
+
+impl QuorumSystem for Majority {
+    fn is_quorum(&self, _phase: Phase, acceptors: &[NodeId], responders: &BTreeSet<NodeId>) -> bool {
+        count(acceptors, responders) > acceptors.len() / 2
+    }
+}
+
+/// Fixed quorum sizes for each phase. Quorums of the two phases only
+/// intersect while `phase1 + phase2` exceeds the number of acceptors, so a
+/// configuration that grows past that after a membership change has no
+/// quorums at all.
+#[derive(Debug, Clone, Copy)]
+pub struct Flexible {
+    phase1: usize,
+    phase2: usize,
+}
+
+impl Flexible {
+    /// Quorum sizes for a cluster of `acceptors` acceptors. Each size must be
+    /// between 1 and `acceptors`, and together they must exceed it.
+    pub fn new(acceptors: usize, phase1: usize, phase2: usize) -> Result<Self, QuorumError> {
+        for (phase, size) in [(Phase::One, phase1), (Phase::Two, phase2)] {
+            if size == 0 || size > acceptors {
+                return Err(QuorumError::SizeOutOfRange(phase, size, acceptors));
+            }
+        }
+        if phase1 + phase2 <= acceptors {
+            return Err(QuorumError::NoIntersection(phase1, phase2, acceptors));
+        }
+        Ok(Flexible { phase1, phase2 })
+    }
+
+    pub fn phase1(&self) -> usize {
+        self.phase1
+    }
+
+    pub fn phase2(&self) -> usize {
+        self.phase2
+    }
+}
+
+impl QuorumSystem for Flexible {
+    fn is_quorum(&self, phase: Phase, acceptors: &[NodeId], responders: &BTreeSet<NodeId>) -> bool {
+        let size = match phase {
+            Phase::One => self.phase1,
+            Phase::Two => self.phase2,
+        };
+        self.phase1 + self.phase2 > acceptors.len() && count(acceptors, responders) >= size
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuorumError {
+    /// The quorum size for the phase is 0 or more than the number of
+    /// acceptors, so the empty set would be a quorum or none would be.
+    SizeOutOfRange(Phase, usize, usize),
+    /// The phase-1 and phase-2 sizes do not add up to more than the number of
+    /// acceptors, so their quorums need not intersect.
+    NoIntersection(usize, usize, usize),
+}
+
+impl fmt::Display for QuorumError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QuorumError::SizeOutOfRange(phase, size, acceptors) => {
+                write!(f, "phase {:?} quorum size {} is not between 1 and {}", phase, size, acceptors)
+            }
+            QuorumError::NoIntersection(phase1, phase2, acceptors) => {
+                write!(f, "quorum sizes {} and {} do not add up to more than {} acceptors", phase1, phase2, acceptors)
+            }
+        }
+    }
+}
+
+impl std::error::Error for QuorumError {}
+
+/// Acceptors laid out in rows. Phase 1 needs every acceptor of some row and
+/// phase 2 one acceptor from every row, so phase 2 costs one reply per row.
+/// The grid only has quorums for a configuration of exactly its acceptors;
+/// after a membership change that adds or removes any, there are none.
+#[derive(Debug, Clone)]
+pub struct Grid {
+    rows: Vec<Vec<NodeId>>,
+}
+
+impl Grid {
+    pub fn new(rows: Vec<Vec<NodeId>>) -> Self {
+        Grid { rows }
+    }
+}
+
+impl QuorumSystem for Grid {
+    fn is_quorum(&self, phase: Phase, acceptors: &[NodeId], responders: &BTreeSet<NodeId>) -> bool {
+        let members: BTreeSet<NodeId> = self.rows.iter().flatten().copied().collect();
+        if members != acceptors.iter().copied().collect() {
+            return false;
+        }
+        let answered = |acceptor: &NodeId| responders.contains(acceptor);
+        match phase {
+            Phase::One => self.rows.iter().any(|row| !row.is_empty() && row.iter().all(answered)),
+            Phase::Two => !self.rows.is_empty() && self.rows.iter().all(|row| row.iter().any(answered)),
+        }
+    }
+}
+
+/// More than half of the acceptors' total weight, in both phases. Acceptors
+/// without a weight count once.
+#[derive(Debug, Clone)]
+pub struct Weighted {
+    weights: BTreeMap<NodeId, u64>,
+}
+
+impl Weighted {
+    pub fn new(weights: BTreeMap<NodeId, u64>) -> Self {
+        Weighted { weights }
+    }
+
+    fn weight(&self, acceptor: &NodeId) -> u64 {
+        self.weights.get(acceptor).copied().unwrap_or(1)
+    }
+}
+
+impl QuorumSystem for Weighted {
+    fn is_quorum(&self, _phase: Phase, acceptors: &[NodeId], responders: &BTreeSet<NodeId>) -> bool {
+        let total: u64 = acceptors.iter().map(|acceptor| self.weight(acceptor)).sum();
+        let answered: u64 =
+            acceptors.iter().filter(|acceptor| responders.contains(acceptor)).map(|acceptor| self.weight(acceptor)).sum();
+        answered > total / 2
+    }
+}
+
+/// A shared quorum system, as held by every node of a cluster.
+#[derive(Debug, Clone)]
+pub struct Quorums(Arc<dyn QuorumSystem>);
+
+impl Quorums {
+    pub fn new(system: impl QuorumSystem + 'static) -> Self {
+        Quorums(Arc::new(system))
+    }
+
+    /// Whether the acceptors outside `rejecters` can no longer make a quorum.
+    pub fn is_blocked(&self, phase: Phase, acceptors: &[NodeId], rejecters: &BTreeSet<NodeId>) -> bool {
+        let rest = acceptors.iter().filter(|acceptor| !rejecters.contains(acceptor)).copied().collect();
+        !self.is_quorum(phase, acceptors, &rest)
+    }
+}
+
+impl QuorumSystem for Quorums {
+    fn is_quorum(&self, phase: Phase, acceptors: &[NodeId], responders: &BTreeSet<NodeId>) -> bool {
+        self.0.is_quorum(phase, acceptors, responders)
+    }
+}
+
+impl Default for Quorums {
+    fn default() -> Self {
+        Quorums::new(Majority)
+    }
+}
+
+// Every node uses the quorum system it was built with for its whole life, so
+// it is configuration rather than state and is ignored when nodes are
+// compared or hashed.
+impl PartialEq for Quorums {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Quorums {}
+
+impl Hash for Quorums {
+    fn hash<H: Hasher>(&self, _state: &mut H) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(ids: &[NodeId]) -> BTreeSet<NodeId> {
+        ids.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_quorum_systems() {
+        let acceptors = [1, 2, 3, 4];
+        assert!(!Majority.is_quorum(Phase::One, &acceptors, &set(&[1, 2])));
+        assert!(Majority.is_quorum(Phase::Two, &acceptors, &set(&[1, 2, 4])));
+        assert!(!Majority.is_quorum(Phase::Two, &acceptors, &set(&[1, 2, 9])));
+
+        let flexible = Flexible::new(4, 3, 2).unwrap();
+        assert!(flexible.is_quorum(Phase::Two, &acceptors, &set(&[3, 4])));
+        assert!(!flexible.is_quorum(Phase::One, &acceptors, &set(&[3, 4])));
+
+        let grid = Grid::new(vec![vec![1, 2], vec![3, 4]]);
+        assert!(grid.is_quorum(Phase::One, &acceptors, &set(&[3, 4])));
+        assert!(!grid.is_quorum(Phase::One, &acceptors, &set(&[1, 3])));
+        assert!(grid.is_quorum(Phase::Two, &acceptors, &set(&[1, 3])));
+        assert!(!grid.is_quorum(Phase::Two, &acceptors, &set(&[1, 2])));
+
+        let weighted = Weighted::new(BTreeMap::from([(1, 3)]));
+        assert!(weighted.is_quorum(Phase::One, &acceptors, &set(&[1, 2])));
+        assert!(!weighted.is_quorum(Phase::One, &acceptors, &set(&[2, 3, 4])));
+
+        // Quorums of one and three of four acceptors need not intersect, an
+        // empty quorum intersects nothing, and one larger than the cluster
+        // can never be reached.
+        assert_eq!(Flexible::new(4, 1, 3).unwrap_err(), QuorumError::NoIntersection(1, 3, 4));
+        assert_eq!(Flexible::new(4, 0, 5).unwrap_err(), QuorumError::SizeOutOfRange(Phase::One, 0, 4));
+        assert_eq!(Flexible::new(4, 4, 0).unwrap_err(), QuorumError::SizeOutOfRange(Phase::Two, 0, 4));
+        assert_eq!(Flexible::new(4, 5, 1).unwrap_err(), QuorumError::SizeOutOfRange(Phase::One, 5, 4));
+
+        // Safe for three acceptors, but not once a fourth has joined.
+        let grown = Flexible::new(3, 1, 3).unwrap();
+        assert!(grown.is_quorum(Phase::Two, &[1, 2, 3], &set(&[1, 2, 3])));
+        assert!(!grown.is_quorum(Phase::One, &acceptors, &set(&[1, 2, 3, 4])));
+        assert!(!grown.is_quorum(Phase::Two, &acceptors, &set(&[1, 2, 3, 4])));
+
+        // Acceptor 5 replaced 4, which the grid does not know of.
+        assert!(!grid.is_quorum(Phase::One, &[1, 2, 3, 5], &set(&[1, 2, 3, 5])));
+        assert!(!grid.is_quorum(Phase::Two, &[1, 2, 3], &set(&[1, 2, 3])));
+
+        let quorums = Quorums::new(flexible);
+        assert!(!quorums.is_blocked(Phase::Two, &acceptors, &set(&[1, 2])));
+        assert!(quorums.is_blocked(Phase::Two, &acceptors, &set(&[1, 2, 3])));
+        assert!(quorums.is_blocked(Phase::One, &acceptors, &set(&[1, 2])));
+    }
+}
@@ -0,0 +1,247 @@
+//! A client of a running TCP cluster that waits for answers, unlike
+//! `Client`, which only hands values to a proposer.
+//!
+//! The client listens on the address of a `client` node of the manifest so
+//! the nodes can reply to it. It submits values with `Submit` and waits for
+//! the proposer's `Committed`, learns decisions the way a lagging learner
+//! does, by sending `CatchUp` to a learner, and reads acceptors' state with
+//! `Inspect`.
+use std::fmt;
+use std::io;
+use std::time::{Duration, Instant};
+use crate::acceptor::AcceptorState;
+use crate::learner::{Log, SnapshotParts, CATCH_UP_BATCH};
+use crate::manifest::{Manifest, ManifestError, Role};
+use crate::message::Message;
+use crate::tcp::TcpTransport;
+use crate::transport::{NodeId, Transport};
+use crate::value::Value;
+
+/// How long a learner must stay silent before its reply to a catch-up
+/// request is taken to be complete. Learners send nothing for slots they do
+/// not have, so silence is the only sign that they are done.
+const QUIET: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+pub enum RemoteError {
+    Manifest(ManifestError),
+    Io(io::Error),
+    /// The node did not answer in time.
+    NoReply(NodeId),
+    /// The value was not decided in time. It may still be decided later.
+    Timeout,
+}
+
+impl fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RemoteError::Manifest(error) => write!(f, "{}", error),
+            RemoteError::Io(error) => write!(f, "{}", error),
+            RemoteError::NoReply(id) => write!(f, "node {} did not answer in time", id),
+            RemoteError::Timeout => write!(f, "value was not decided in time"),
+        }
+    }
+}
+
+impl std::error::Error for RemoteError {}
+
+impl From<ManifestError> for RemoteError {
+    fn from(error: ManifestError) -> Self {
+        RemoteError::Manifest(error)
+    }
+}
+
+impl From<io::Error> for RemoteError {
+    fn from(error: io::Error) -> Self {
+        RemoteError::Io(error)
+    }
+}
+
+pub struct RemoteClient<V = String> {
+    id: NodeId,
+    manifest: Manifest,
+    transport: TcpTransport<V>,
+    /// Proposer that values are submitted to.
+    proposer: NodeId,
+    /// Learner asked which values were decided.
+    learner: NodeId,
+    /// The decided log as far as this client has seen it.
+    log: Log<V>,
+    /// Request id of the next `submit`.
+    next_request: u64,
+}
+
+impl<V: Value> RemoteClient<V> {
+    /// Listens as client `id` of `manifest`, submitting values to its first
+    /// proposer and learning decisions from its first learner.
+    pub fn connect(manifest: Manifest, id: NodeId) -> Result<Self, RemoteError> {
+        let node = manifest.node(id)?;
+        if node.role != Role::Client {
+            return Err(ManifestError::WrongRole(id, Role::Client).into());
+        }
+        let transport = manifest.transport(id, node.address)?;
+        let proposer = manifest.ids(Role::Proposer)[0];
+        let learner = manifest.ids(Role::Learner)[0];
+        Ok(RemoteClient { id, manifest, transport, proposer, learner, log: Log::default(), next_request: 1 })
+    }
+
+    /// Submits values to proposer `id` instead.
+    pub fn with_proposer(mut self, id: NodeId) -> Result<Self, RemoteError> {
+        self.proposer = self.check_role(id, Role::Proposer)?;
+        Ok(self)
+    }
+
+    /// Learns decisions from learner `id` instead.
+    pub fn with_learner(mut self, id: NodeId) -> Result<Self, RemoteError> {
+        self.learner = self.check_role(id, Role::Learner)?;
+        Ok(self)
+    }
+
+    fn check_role(&self, id: NodeId, role: Role) -> Result<NodeId, RemoteError> {
+        if self.manifest.node(id)?.role != role {
+            return Err(ManifestError::WrongRole(id, role).into());
+        }
+        Ok(id)
+    }
+
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// The decided log as of the last `sync`.
+    pub fn log(&self) -> &Log<V> {
+        &self.log
+    }
+
+    /// Fetches the decisions made since the last call.
+    pub fn sync(&mut self) -> Result<(), RemoteError> {
+        let log = std::mem::take(&mut self.log);
+        self.log = self.fetch(self.learner, log)?;
+        Ok(())
+    }
+
+    /// Submits `value` and waits up to `timeout` for it to be decided.
+    /// Returns the slot it was decided in, as reported by the proposer under
+    /// this submission's own request id.
+    pub fn submit(&mut self, value: V, timeout: Duration) -> Result<u64, RemoteError> {
+        let deadline = Instant::now() + timeout;
+        let id = self.next_request;
+        self.next_request += 1;
+        self.transport.send(self.proposer, Message::Submit(self.id, id, value))?;
+        while let Some(message) = self.transport.recv_deadline(Some(deadline))? {
+            if let Message::Committed(committed, slot) = message {
+                if committed == id {
+                    return Ok(slot);
+                }
+            }
+        }
+        Err(RemoteError::Timeout)
+    }
+
+    /// Everything acceptor `id` holds.
+    pub fn acceptor_state(&self, id: NodeId, timeout: Duration) -> Result<AcceptorState<V>, RemoteError> {
+        self.check_role(id, Role::Acceptor)?;
+        self.transport.send(id, Message::Inspect(self.id))?;
+        let deadline = Instant::now() + timeout;
+        while let Some(message) = self.transport.recv_deadline(Some(deadline))? {
+            if let Message::InspectReply(acceptor_id, promised, truncated, slots) = message {
+                if acceptor_id != id {
+                    continue;
+                }
+                return Ok(AcceptorState { promised, slots: slots.into_iter().collect(), truncated });
+            }
+        }
+        Err(RemoteError::NoReply(id))
+    }
+
+    /// Learner `id`'s log: its snapshot, if any, and the decided entries
+    /// after it. A learner that has decided nothing sends nothing, so its
+    /// log comes back empty after a short wait.
+    pub fn learner_log(&self, id: NodeId) -> Result<Log<V>, RemoteError> {
+        self.check_role(id, Role::Learner)?;
+        self.fetch(id, Log::default())
+    }
+
+    /// Asks `learner` for the entries after `log` and appends them, up to
+    /// `CATCH_UP_BATCH` slots at a time, until a request brings nothing new.
+    fn fetch(&self, learner: NodeId, mut log: Log<V>) -> Result<Log<V>, RemoteError> {
+        let mut snapshot = SnapshotParts::default();
+        loop {
+            let start = log.len();
+            self.transport.send(learner, Message::CatchUp(self.id, start, start.saturating_add(CATCH_UP_BATCH)))?;
+            while let Some(message) = self.transport.recv_deadline(Some(Instant::now() + QUIET))? {
+                match message {
+                    Message::Snapshot(index, offset, len, part) => {
+                        if let Some(snapshot) = snapshot.add(index, offset, len, part) {
+                            log.compact(snapshot);
+                        }
+                    }
+                    Message::CatchUpReply(entries) => {
+                        for (slot, value) in entries {
+                            if slot == log.len() {
+                                log.push(value);
+                            }
+                        }
+                        // The reply comes last, so the request is answered.
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            if log.len() == start {
+                return Ok(log);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::learner::Storage;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_submit_and_inspect_over_tcp() {
+        let roles = ["proposer", "acceptor", "acceptor", "acceptor", "learner", "client"];
+        // Ports the OS just handed out, so most likely still free.
+        let listeners: Vec<TcpListener> = roles.iter().map(|_| TcpListener::bind("127.0.0.1:0").unwrap()).collect();
+        let nodes: String = roles
+            .iter()
+            .zip(&listeners)
+            .enumerate()
+            .map(|(id, (role, listener))| {
+                format!("[[nodes]]\nid = {}\nrole = \"{}\"\naddress = \"{}\"\n", id, role, listener.local_addr().unwrap())
+            })
+            .collect();
+        drop(listeners);
+        let manifest = Manifest::parse(&nodes).unwrap();
+        let storage: Storage = Storage::default();
+        let handles: Vec<_> = (0..5).map(|id| manifest.spawn(id, &storage).unwrap()).collect();
+
+        let mut client = RemoteClient::connect(manifest, 5).unwrap();
+        let timeout = Duration::from_secs(5);
+        assert_eq!(client.submit("a".to_string(), timeout).unwrap(), 0);
+        assert_eq!(client.submit("b".to_string(), timeout).unwrap(), 1);
+        assert_eq!(client.submit("a".to_string(), timeout).unwrap(), 2);
+
+        let state = client.acceptor_state(2, timeout).unwrap();
+        assert_eq!(state.slots[&1].accepted.as_ref().map(|(_, value)| value.as_str()), Some("b"));
+        let log = client.learner_log(4).unwrap();
+        assert_eq!(log.entries(), &[(0, "a".to_string()), (1, "b".to_string()), (2, "a".to_string())]);
+        client.sync().unwrap();
+        assert_eq!(client.log().len(), 3);
+        assert!(matches!(client.acceptor_state(4, timeout), Err(RemoteError::Manifest(ManifestError::WrongRole(4, Role::Acceptor)))));
+
+        for id in 0..5 {
+            client.transport.send(id, Message::Terminate).unwrap();
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+}
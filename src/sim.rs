@@ -0,0 +1,483 @@
+//! Deterministic discrete-event simulation of a whole cluster.
+//!
+//! Every node runs in the calling thread and messages travel through an event
+//! queue ordered by a virtual clock. Message latencies are drawn from a seeded
+//! `Rng`, so a seed fully determines the interleaving and any run can be
+//! replayed exactly. A `FaultConfig` can add drops, duplicates, delays and
+//! partitions on top.
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::io;
+use std::time::{Duration, Instant};
+use crate::acceptor::Acceptor;
+use crate::failure_detector::{FailureDetector, HeartbeatConfig};
+use crate::fault::FaultConfig;
+use crate::history::History;
+use crate::learner::{Learner, Storage};
+use crate::message::Message;
+use crate::node::{handle_acceptor, handle_learner, handle_proposer};
+use crate::proposer::{Proposer, RetryConfig};
+use crate::rng::Rng;
+use crate::transport::{NodeId, Transport};
+
+/// Configures a `Simulation`. Node ids follow the same layout as `Cluster`.
+pub struct SimulationBuilder {
+    seed: u64,
+    proposers: usize,
+    acceptors: usize,
+    learners: usize,
+    min_latency: Duration,
+    max_latency: Duration,
+    retry: RetryConfig,
+    faults: FaultConfig,
+    heartbeats: Option<HeartbeatConfig>,
+    catch_up: Option<Duration>,
+}
+
+impl Default for SimulationBuilder {
+    fn default() -> Self {
+        SimulationBuilder {
+            seed: 0,
+            proposers: 1,
+            acceptors: 3,
+            learners: 1,
+            min_latency: Duration::from_millis(1),
+            max_latency: Duration::from_millis(10),
+            retry: RetryConfig::default(),
+            faults: FaultConfig::default(),
+            heartbeats: None,
+            catch_up: None,
+        }
+    }
+}
+
+impl SimulationBuilder {
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn proposers(mut self, proposers: usize) -> Self {
+        self.proposers = proposers;
+        self
+    }
+
+    pub fn acceptors(mut self, acceptors: usize) -> Self {
+        self.acceptors = acceptors;
+        self
+    }
+
+    pub fn learners(mut self, learners: usize) -> Self {
+        self.learners = learners;
+        self
+    }
+
+    /// Each message is delivered after a latency drawn uniformly from `min..=max`.
+    pub fn latency(mut self, min: Duration, max: Duration) -> Self {
+        self.min_latency = min;
+        self.max_latency = max.max(min);
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryConfig) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn faults(mut self, faults: FaultConfig) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    /// Gives every node a failure detector. Nodes then always have a timer
+    /// pending, so runs only end at the `run_for` limit.
+    pub fn heartbeats(mut self, heartbeats: HeartbeatConfig) -> Self {
+        self.heartbeats = Some(heartbeats);
+        self
+    }
+
+    /// Has learners ask each other and the acceptors for missed decisions
+    /// every `interval`. Like heartbeats, this keeps a timer pending.
+    pub fn catch_up(mut self, interval: Duration) -> Self {
+        self.catch_up = Some(interval);
+        self
+    }
+
+    pub fn build(self) -> Simulation {
+        let proposer_ids: Vec<NodeId> = (0..self.proposers as u64).collect();
+        let acceptor_ids: Vec<NodeId> = (0..self.acceptors as u64).map(|i| i + self.proposers as u64).collect();
+        let learner_ids: Vec<NodeId> = (0..self.learners as u64).map(|i| i + (self.proposers + self.acceptors) as u64).collect();
+        let ids: Vec<NodeId> = proposer_ids.iter().chain(&acceptor_ids).chain(&learner_ids).copied().collect();
+        let start = Instant::now();
+        let detector = |id: NodeId| self.heartbeats.clone().map(|config| FailureDetector::new(id, ids.clone(), config, start));
+        Simulation {
+            start,
+            elapsed: Duration::ZERO,
+            rng: Rng::new(self.seed),
+            min_latency: self.min_latency,
+            max_latency: self.max_latency,
+            faults: self.faults,
+            queue: BinaryHeap::new(),
+            sequence: 0,
+            trace: vec![],
+            history: History::new(),
+            observed: 0,
+            proposers: proposer_ids
+                .iter()
+                .map(|id| {
+                    let mut proposer = Proposer::new(*id, acceptor_ids.clone()).with_retry_config(self.retry.clone());
+                    if let Some(detector) = detector(*id) {
+                        proposer = proposer.with_failure_detector(detector);
+                    }
+                    (*id, proposer)
+                })
+                .collect(),
+            acceptors: acceptor_ids
+                .iter()
+                .map(|id| {
+                    let mut acceptor = Acceptor::new(*id).with_learners(learner_ids.clone());
+                    if let Some(detector) = detector(*id) {
+                        acceptor = acceptor.with_failure_detector(detector);
+                    }
+                    (*id, acceptor)
+                })
+                .collect(),
+            learners: learner_ids
+                .iter()
+                .map(|id| {
+                    let mut learner = Learner::new(*id, proposer_ids.clone(), acceptor_ids.clone());
+                    if let Some(interval) = self.catch_up {
+                        learner = learner.with_catch_up(learner_ids.clone(), interval, start);
+                    }
+                    if let Some(detector) = detector(*id) {
+                        learner = learner.with_failure_detector(detector);
+                    }
+                    (*id, (learner, Storage::default()))
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A message waiting in the event queue, ordered by delivery time and then
+/// by the order it was sent in.
+struct Event {
+    at: Duration,
+    sequence: u64,
+    from: Option<NodeId>,
+    to: NodeId,
+    message: Message,
+}
+
+impl PartialEq for Event {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.sequence) == (other.at, other.sequence)
+    }
+}
+
+impl Eq for Event {}
+
+impl PartialOrd for Event {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Event {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.at, self.sequence).cmp(&(other.at, other.sequence))
+    }
+}
+
+/// Collects what a node sends while handling one event.
+struct SimTransport {
+    id: NodeId,
+    outbox: RefCell<Vec<(NodeId, Message)>>,
+}
+
+impl Transport for SimTransport {
+    fn id(&self) -> NodeId {
+        self.id
+    }
+
+    fn send(&self, to: NodeId, message: Message) -> io::Result<()> {
+        self.outbox.borrow_mut().push((to, message));
+        Ok(())
+    }
+
+    fn recv_deadline(&self, _deadline: Option<Instant>) -> io::Result<Option<Message>> {
+        Ok(None)
+    }
+}
+
+pub struct Simulation {
+    start: Instant,
+    elapsed: Duration,
+    rng: Rng,
+    min_latency: Duration,
+    max_latency: Duration,
+    faults: FaultConfig,
+    queue: BinaryHeap<Reverse<Event>>,
+    sequence: u64,
+    trace: Vec<String>,
+    history: History<String, u64>,
+    /// Length of the longest learner log folded into `history`.
+    observed: u64,
+    proposers: BTreeMap<NodeId, Proposer>,
+    acceptors: BTreeMap<NodeId, Acceptor>,
+    learners: BTreeMap<NodeId, (Learner, Storage)>,
+}
+
+impl Simulation {
+    pub fn builder() -> SimulationBuilder {
+        SimulationBuilder::default()
+    }
+
+    /// Virtual time since the simulation started.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Faults applied to messages sent from now on, e.g. to start or heal a partition.
+    pub fn faults_mut(&mut self) -> &mut FaultConfig {
+        &mut self.faults
+    }
+
+    /// One line per delivered or dropped message and fired timer, in
+    /// execution order.
+    pub fn trace(&self) -> &[String] {
+        &self.trace
+    }
+
+    /// Every submitted value, completed with its slot once the first learner
+    /// decides it.
+    pub fn history(&self) -> &History<String, u64> {
+        &self.history
+    }
+
+    /// Decided log of learner `id`.
+    pub fn log(&self, id: NodeId) -> Vec<(u64, String)> {
+        self.learners[&id].1.lock().unwrap().entries().to_vec()
+    }
+
+    pub fn learner_ids(&self) -> Vec<NodeId> {
+        self.learners.keys().copied().collect()
+    }
+
+    /// Delivers a client request for `value` to proposer `id` right away.
+    pub fn submit(&mut self, id: NodeId, value: impl Into<String>) {
+        let value = value.into();
+        self.history.invoke(id, value.clone(), self.elapsed);
+        self.schedule(None, id, Message::Consensus(0, value), Duration::ZERO);
+    }
+
+    fn schedule(&mut self, from: Option<NodeId>, to: NodeId, message: Message, delay: Duration) {
+        self.sequence += 1;
+        self.queue.push(Reverse(Event { at: self.elapsed + delay, sequence: self.sequence, from, to, message }));
+    }
+
+    fn latency(&mut self) -> Duration {
+        let spread = (self.max_latency - self.min_latency).as_micros() as u64;
+        self.min_latency + Duration::from_micros(self.rng.below(spread + 1))
+    }
+
+    /// Earliest pending timer of any node, as an offset from the start.
+    fn next_timer(&self) -> Option<(Duration, NodeId)> {
+        let proposers = self.proposers.iter().map(|(id, proposer)| (*id, proposer.next_deadline()));
+        let acceptors = self.acceptors.iter().map(|(id, acceptor)| (*id, acceptor.next_deadline()));
+        let learners = self.learners.iter().map(|(id, (learner, _))| (*id, learner.next_deadline()));
+        proposers
+            .chain(acceptors)
+            .chain(learners)
+            .filter_map(|(id, deadline)| deadline.map(|deadline| (deadline.saturating_duration_since(self.start), id)))
+            .min()
+    }
+
+    /// Runs the next event or timer. Returns `false` when nothing is left to do.
+    pub fn step(&mut self) -> bool {
+        let next_event = self.queue.peek().map(|Reverse(event)| event.at);
+        let transport = match (next_event, self.next_timer()) {
+            (None, None) => return false,
+            (Some(at), Some((timer, id))) if timer < at => self.fire_timer(timer, id),
+            (None, Some((timer, id))) => self.fire_timer(timer, id),
+            _ => {
+                let Reverse(event) = self.queue.pop().unwrap();
+                self.deliver(event)
+            }
+        };
+        for (to, message) in transport.outbox.into_inner() {
+            let delays = self.faults.plan(transport.id, to, &mut self.rng);
+            if delays.is_empty() {
+                self.trace.push(format!("{:>8?} {} -x {} {}", self.elapsed, transport.id, to, message));
+            }
+            for extra in delays {
+                let delay = self.latency() + extra;
+                self.schedule(Some(transport.id), to, message.clone(), delay);
+            }
+        }
+        true
+    }
+
+    fn fire_timer(&mut self, at: Duration, id: NodeId) -> SimTransport {
+        self.elapsed = self.elapsed.max(at);
+        self.trace.push(format!("{:>8?} timer {}", self.elapsed, id));
+        let transport = SimTransport { id, outbox: RefCell::new(vec![]) };
+        let now = self.start + self.elapsed;
+        if let Some(proposer) = self.proposers.get_mut(&id) {
+            proposer.tick(&transport, now);
+        } else if let Some(acceptor) = self.acceptors.get_mut(&id) {
+            acceptor.tick(&transport, now);
+        } else if let Some((learner, storage)) = self.learners.get_mut(&id) {
+            learner.tick(storage, &transport, now);
+        }
+        transport
+    }
+
+    fn deliver(&mut self, event: Event) -> SimTransport {
+        self.elapsed = event.at;
+        let from = event.from.map_or("client".to_string(), |id| id.to_string());
+        self.trace.push(format!("{:>8?} {} -> {} {}", self.elapsed, from, event.to, event.message));
+        let transport = SimTransport { id: event.to, outbox: RefCell::new(vec![]) };
+        let now = self.start + self.elapsed;
+        if let Some(proposer) = self.proposers.get_mut(&event.to) {
+            handle_proposer(proposer, event.message, &transport, now);
+        } else if let Some(acceptor) = self.acceptors.get_mut(&event.to) {
+            handle_acceptor(acceptor, event.message, &transport, now);
+        } else if let Some((learner, storage)) = self.learners.get_mut(&event.to) {
+            handle_learner(learner, event.message, storage, &transport, now);
+            let log = storage.lock().unwrap();
+            for (slot, value) in log.range(self.observed, u64::MAX) {
+                self.history.complete_append(*slot, value, self.elapsed);
+            }
+            self.observed = self.observed.max(log.len());
+        }
+        transport
+    }
+
+    /// Steps until nothing is left to do or virtual time passes `limit`.
+    pub fn run_for(&mut self, limit: Duration) {
+        while self.next_time().is_some_and(|at| at <= limit) {
+            self.step();
+        }
+    }
+
+    fn next_time(&self) -> Option<Duration> {
+        let next_event = self.queue.peek().map(|Reverse(event)| event.at);
+        let next_timer = self.next_timer().map(|(at, _)| at);
+        next_event.into_iter().chain(next_timer).min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fault::LinkFaults;
+    use crate::linearizability::{check, AppendLog};
+
+    fn run(seed: u64) -> Simulation {
+        let mut sim = Simulation::builder().seed(seed).proposers(2).learners(2).build();
+        sim.submit(0, "a");
+        sim.submit(1, "b");
+        sim.submit(0, "c");
+        sim.run_for(Duration::from_secs(60));
+        sim
+    }
+
+    #[test]
+    fn test_same_seed_same_run() {
+        let first = run(7);
+        let second = run(7);
+        assert_eq!(first.trace(), second.trace());
+        assert_ne!(first.trace(), run(8).trace());
+    }
+
+    #[test]
+    fn test_learners_agree_across_seeds() {
+        for seed in 0..50 {
+            let sim = run(seed);
+            let logs: Vec<_> = sim.learner_ids().into_iter().map(|id| sim.log(id)).collect();
+            assert_eq!(logs[0], logs[1], "seed {}", seed);
+            for value in ["a", "b", "c"] {
+                assert!(logs[0].iter().any(|(_, v)| v == value), "seed {} lost {}", seed, value);
+            }
+        }
+    }
+
+    #[test]
+    fn test_faults_do_not_break_agreement() {
+        let faults = LinkFaults { drop: 0.1, duplicate: 0.1, reorder: 0.2, max_delay: Duration::from_millis(5), ..LinkFaults::default() };
+        for seed in 0..50 {
+            let mut sim = Simulation::builder().seed(seed).proposers(2).learners(2).faults(FaultConfig::new(faults.clone())).build();
+            sim.submit(0, "a");
+            sim.submit(1, "b");
+            sim.run_for(Duration::from_secs(60));
+            let (first, second) = (sim.log(5), sim.log(6));
+            let common = first.len().min(second.len());
+            assert_eq!(first[..common], second[..common], "seed {}", seed);
+            if let Err(counterexample) = check(&AppendLog, sim.history()) {
+                panic!("seed {}: {}", seed, counterexample);
+            }
+        }
+    }
+
+    #[test]
+    fn test_partitioned_acceptor_majority_still_decides() {
+        let mut sim = Simulation::builder().acceptors(5).build();
+        sim.faults_mut().partition(&[0], &[1, 2]);
+        sim.submit(0, "a");
+        sim.run_for(Duration::from_secs(1));
+        assert_eq!(sim.log(6), vec![(0, "a".to_string())]);
+        assert!(sim.trace().iter().any(|line| line.contains("-x")));
+    }
+
+    #[test]
+    fn test_partitioned_learner_catches_up() {
+        let mut sim = Simulation::builder().learners(2).catch_up(Duration::from_millis(500)).build();
+        sim.faults_mut().partition(&[5], &[0, 1, 2, 3, 4]);
+        sim.submit(0, "a");
+        sim.submit(0, "b");
+        sim.run_for(Duration::from_secs(1));
+        assert_eq!(sim.log(4).len(), 2);
+        assert!(sim.log(5).is_empty());
+
+        sim.faults_mut().heal();
+        sim.run_for(Duration::from_secs(2));
+        assert_eq!(sim.log(5), sim.log(4));
+        assert!(sim.trace().iter().any(|line| line.contains("5 -> 4 CatchUp")));
+        assert!(sim.trace().iter().any(|line| line.contains("4 -> 5 CatchUpReply")));
+    }
+
+    #[test]
+    fn test_leader_skips_phase_one_for_later_values() {
+        let mut sim = Simulation::builder().build();
+        sim.submit(0, "a");
+        sim.run_for(Duration::from_secs(1));
+        let elected = sim.trace().len();
+        sim.submit(0, "b");
+        sim.run_for(Duration::from_secs(2));
+
+        assert_eq!(sim.log(4), vec![(0, "a".to_string()), (1, "b".to_string())]);
+        let steady = &sim.trace()[elected..];
+        assert!(steady.iter().all(|line| !line.contains("Prepare")), "{:#?}", steady);
+        assert_eq!(steady.iter().filter(|line| line.contains("Propose")).count(), 3);
+    }
+
+    #[test]
+    fn test_followers_forward_to_leader_until_it_is_suspected() {
+        let mut sim = Simulation::builder().proposers(2).heartbeats(HeartbeatConfig::default()).build();
+        sim.submit(0, "a");
+        sim.run_for(Duration::from_secs(1));
+        sim.submit(1, "b");
+        sim.run_for(Duration::from_secs(2));
+        assert!(sim.trace().iter().any(|line| line.ends_with("1 -> 0 Consensus(0, \"b\")")));
+        assert!(!sim.trace().iter().any(|line| line.contains("1 -> 2 PrepareAll")));
+
+        sim.faults_mut().partition(&[0], &[1, 2, 3, 4, 5]);
+        sim.run_for(Duration::from_secs(3));
+        sim.submit(1, "c");
+        sim.run_for(Duration::from_secs(4));
+        let values: Vec<_> = sim.log(5).into_iter().map(|(_, value)| value).collect();
+        assert_eq!(values, vec!["a", "b", "c"]);
+    }
+}
@@ -0,0 +1,167 @@
+//! Replicated state machines driven by the decided log.
+//!
+//! Clients wrap each command in a `Request` that names the client and a
+//! sequence number, and the cluster agrees on requests. Learners apply every
+//! decided command to a `StateMachine` in slot order, exactly once, and
+//! publish its output to `Outputs`, where the client that sent the command
+//! waits for it. Learners can also snapshot the machine so the log below the
+//! snapshot can be discarded.
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use crate::codec::CodecError;
+use crate::membership::Configuration;
+use crate::value::Value;
+
+/// Deterministic state replicated by applying the same commands in the same
+/// order on every replica.
+pub trait StateMachine<C>: Send + 'static {
+    type Output: Clone + Send + 'static;
+
+    /// Applies the command decided in `slot`.
+    fn apply(&mut self, slot: u64, command: &C) -> Self::Output;
+
+    /// Serializes the current state.
+    fn snapshot(&self) -> Vec<u8>;
+
+    /// Replaces the current state with one produced by `snapshot`.
+    fn restore(&mut self, state: &[u8]) -> Result<(), CodecError>;
+}
+
+/// Lets learners that share a log also share the machine it drives.
+impl<C, S: StateMachine<C>> StateMachine<C> for Arc<Mutex<S>> {
+    type Output = S::Output;
+
+    fn apply(&mut self, slot: u64, command: &C) -> Self::Output {
+        self.lock().unwrap().apply(slot, command)
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        self.lock().unwrap().snapshot()
+    }
+
+    fn restore(&mut self, state: &[u8]) -> Result<(), CodecError> {
+        self.lock().unwrap().restore(state)
+    }
+}
+
+/// A command tagged with the client that sent it and a sequence number unique
+/// to that client, so equal commands from different calls can be told apart.
+/// Learners skip a request whose sequence number is not above the last one
+/// applied for its client, so a client sends one request at a time.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Request<C> {
+    pub client: u64,
+    pub seq: u64,
+    pub command: C,
+}
+
+impl<C: Value> Value for Request<C> {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.client.to_le_bytes());
+        bytes.extend_from_slice(&self.seq.to_le_bytes());
+        self.command.encode(bytes);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, CodecError> {
+        if bytes.len() < 16 {
+            return Err(CodecError::Truncated);
+        }
+        let (ids, command) = bytes.split_at(16);
+        Ok(Request {
+            client: u64::from_le_bytes(ids[..8].try_into().unwrap()),
+            seq: u64::from_le_bytes(ids[8..].try_into().unwrap()),
+            command: C::decode(command)?,
+        })
+    }
+
+    fn reconfiguration(&self) -> Option<&Configuration> {
+        self.command.reconfiguration()
+    }
+}
+
+/// Slot and output of each applied request, keyed by client and sequence
+/// number.
+type Published<O> = Mutex<HashMap<(u64, u64), (u64, O)>>;
+
+/// Outputs of applied requests, keyed by client and sequence number, shared
+/// between the learners that produce them and the clients waiting for them.
+/// Only the first output for a request is kept, so learners applying the same
+/// log may all publish.
+pub struct Outputs<O> {
+    state: Arc<(Published<O>, Condvar)>,
+}
+
+impl<O> Clone for Outputs<O> {
+    fn clone(&self) -> Self {
+        Outputs { state: self.state.clone() }
+    }
+}
+
+impl<O> Default for Outputs<O> {
+    fn default() -> Self {
+        Outputs { state: Arc::new((Mutex::new(HashMap::new()), Condvar::new())) }
+    }
+}
+
+impl<O: Clone> Outputs<O> {
+    pub fn new() -> Self {
+        Outputs::default()
+    }
+
+    /// Records that request `seq` of `client` was decided in `slot` and
+    /// produced `output`.
+    pub fn publish(&self, client: u64, seq: u64, slot: u64, output: O) {
+        let (outputs, published) = &*self.state;
+        outputs.lock().unwrap().entry((client, seq)).or_insert((slot, output));
+        published.notify_all();
+    }
+
+    /// The slot and output of request `seq` of `client`, if it has been
+    /// applied.
+    pub fn get(&self, client: u64, seq: u64) -> Option<(u64, O)> {
+        self.state.0.lock().unwrap().get(&(client, seq)).cloned()
+    }
+
+    /// Waits up to `timeout` for request `seq` of `client` to be applied.
+    pub fn wait(&self, client: u64, seq: u64, timeout: Duration) -> Option<(u64, O)> {
+        let deadline = Instant::now() + timeout;
+        let (outputs, published) = &*self.state;
+        let mut outputs = outputs.lock().unwrap();
+        loop {
+            if let Some(output) = outputs.get(&(client, seq)) {
+                return Some(output.clone());
+            }
+            let remaining = deadline.checked_duration_since(Instant::now())?;
+            outputs = published.wait_timeout(outputs, remaining).unwrap().0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_waiting_client_gets_first_output() {
+        let outputs = Outputs::new();
+        let publisher = outputs.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            publisher.publish(1, 0, 4, "first");
+            publisher.publish(1, 0, 4, "again");
+        });
+
+        assert_eq!(outputs.wait(1, 0, Duration::from_secs(5)), Some((4, "first")));
+        handle.join().unwrap();
+        assert_eq!(outputs.get(1, 0), Some((4, "first")));
+        assert_eq!(outputs.wait(2, 0, Duration::from_millis(10)), None);
+
+        let request = Request { client: 3, seq: 9, command: "put".to_string() };
+        let mut bytes = vec![];
+        request.encode(&mut bytes);
+        assert_eq!(Request::decode(&bytes), Ok(request));
+        assert_eq!(Request::<String>::decode(&bytes[..15]), Err(CodecError::Truncated));
+    }
+}
@@ -0,0 +1,227 @@
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::codec;
+use crate::message::Message;
+use crate::transport::{NodeId, Transport};
+use crate::value::Value;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long a write may block on a peer that stopped reading before the
+/// connection is given up.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long sends to a peer fail straight away after connecting to it
+/// failed, doubled after every further failure up to `MAX_BACKOFF`.
+const MIN_BACKOFF: Duration = Duration::from_millis(50);
+const MAX_BACKOFF: Duration = Duration::from_secs(2);
+
+/// Transport that exchanges `codec` frames over TCP.
+///
+/// A background thread accepts connections and feeds every decoded message
+/// into the node's inbox. Outgoing connections are opened on first use and
+/// reopened after a failed write. A peer that cannot be reached is backed
+/// off, so a node that is down costs its senders one connect timeout rather
+/// than one per message.
+pub struct TcpTransport<V = String> {
+    id: NodeId,
+    local_addr: SocketAddr,
+    inbox: Receiver<Message<V>>,
+    inbox_tx: Sender<Message<V>>,
+    links: Mutex<HashMap<NodeId, Arc<Mutex<Link>>>>,
+    stopped: Arc<AtomicBool>,
+}
+
+/// The outgoing connection to one peer.
+struct Link {
+    addr: SocketAddr,
+    stream: Option<TcpStream>,
+    /// Connecting failed; sends fail until then.
+    retry_at: Option<Instant>,
+    backoff: Duration,
+}
+
+impl Link {
+    fn new(addr: SocketAddr) -> Self {
+        Link { addr, stream: None, retry_at: None, backoff: MIN_BACKOFF }
+    }
+
+    /// Writes `frame` to the open connection, which is dropped on failure.
+    fn write(&mut self, frame: &[u8]) -> io::Result<()> {
+        let stream = self.stream.as_mut().expect("link is connected");
+        let result = stream.write_all(frame);
+        if result.is_err() {
+            self.stream = None;
+        }
+        result
+    }
+}
+
+impl<V: Value> TcpTransport<V> {
+    /// Listens on `addr` for messages addressed to node `id`.
+    pub fn bind(id: NodeId, addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let (inbox_tx, inbox) = unbounded();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let listener_tx = inbox_tx.clone();
+        let listener_stopped = stopped.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if listener_stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(stream) = stream else { continue };
+                let tx = listener_tx.clone();
+                thread::spawn(move || read_messages(stream, tx));
+            }
+        });
+        Ok(TcpTransport { id, local_addr, inbox, inbox_tx, links: Mutex::new(HashMap::new()), stopped })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Registers the address node `id` listens on.
+    pub fn add_peer(&self, id: NodeId, addr: SocketAddr) {
+        self.links.lock().unwrap().insert(id, Arc::new(Mutex::new(Link::new(addr))));
+    }
+
+    /// Writes `frame` to node `to`, connecting first if needed. No lock is
+    /// held while connecting, so sends to other peers, and to this one once
+    /// it is connected, go ahead meanwhile.
+    fn write(&self, to: NodeId, frame: &[u8]) -> io::Result<()> {
+        let link = self
+            .links
+            .lock()
+            .unwrap()
+            .get(&to)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("unknown node {}", to)))?;
+        let addr = {
+            let mut link = link.lock().unwrap();
+            if link.stream.is_some() {
+                return link.write(frame);
+            }
+            if link.retry_at.is_some_and(|retry_at| Instant::now() < retry_at) {
+                return Err(io::Error::new(io::ErrorKind::NotConnected, format!("node {} is unreachable", to)));
+            }
+            link.addr
+        };
+        let connected = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT).and_then(|stream| {
+            stream.set_nodelay(true)?;
+            stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+            Ok(stream)
+        });
+        let mut link = link.lock().unwrap();
+        match connected {
+            Ok(stream) => {
+                link.retry_at = None;
+                link.backoff = MIN_BACKOFF;
+                // Another send may have connected meanwhile; keep its stream.
+                link.stream.get_or_insert(stream);
+                link.write(frame)
+            }
+            Err(error) => {
+                link.retry_at = Some(Instant::now() + link.backoff);
+                link.backoff = (link.backoff * 2).min(MAX_BACKOFF);
+                Err(error)
+            }
+        }
+    }
+}
+
+impl<V: Value> Transport<V> for TcpTransport<V> {
+    fn id(&self) -> NodeId {
+        self.id
+    }
+
+    fn send(&self, to: NodeId, message: Message<V>) -> io::Result<()> {
+        if to == self.id {
+            return self.inbox_tx.send(message).map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "inbox closed"));
+        }
+        self.write(to, &codec::encode_frame(&message)?)
+    }
+
+    fn recv_deadline(&self, deadline: Option<Instant>) -> io::Result<Option<Message<V>>> {
+        match deadline {
+            Some(deadline) => match self.inbox.recv_deadline(deadline) {
+                Ok(message) => Ok(Some(message)),
+                Err(RecvTimeoutError::Timeout) => Ok(None),
+                Err(RecvTimeoutError::Disconnected) => unreachable!("transport holds a sender"),
+            },
+            None => Ok(Some(self.inbox.recv().expect("transport holds a sender"))),
+        }
+    }
+}
+
+impl<V> Drop for TcpTransport<V> {
+    fn drop(&mut self) {
+        // Wake the listener so it notices the flag and exits.
+        self.stopped.store(true, Ordering::SeqCst);
+        let _ = TcpStream::connect_timeout(&self.local_addr, CONNECT_TIMEOUT);
+    }
+}
+
+fn read_messages<V: Value>(mut stream: TcpStream, tx: Sender<Message<V>>) {
+    loop {
+        match codec::read_frame(&mut stream) {
+            Ok(message) => {
+                if tx.send(message).is_err() {
+                    return;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                println!("[Transport] Dropping connection after bad frame: {}", e);
+                return;
+            }
+            Err(_) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ballot::Ballot;
+
+    #[test]
+    fn test_messages_cross_tcp() {
+        let a: TcpTransport = TcpTransport::bind(0, "127.0.0.1:0").unwrap();
+        let b: TcpTransport = TcpTransport::bind(1, "127.0.0.1:0").unwrap();
+        a.add_peer(1, b.local_addr());
+        b.add_peer(0, a.local_addr());
+
+        a.send(1, Message::Prepare(Ballot::new(1, 0), 4, "value".to_string())).unwrap();
+        b.send(0, Message::Nack(Ballot::new(1, 0), 4, Ballot::new(2, 3), 1)).unwrap();
+
+        let deadline = Some(Instant::now() + Duration::from_secs(5));
+        match b.recv_deadline(deadline).unwrap() {
+            Some(Message::Prepare(ballot, 4, value)) => {
+                assert_eq!(ballot, Ballot::new(1, 0));
+                assert_eq!(value, "value");
+            }
+            message => panic!("unexpected {:?}", message),
+        }
+        assert!(matches!(a.recv_deadline(deadline).unwrap(), Some(Message::Nack(_, 4, _, 1))));
+    }
+
+    #[test]
+    fn test_unreachable_peer_is_backed_off() {
+        let a: TcpTransport = TcpTransport::bind(0, "127.0.0.1:0").unwrap();
+        // A port the OS just handed out and nobody listens on anymore.
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        a.add_peer(1, closed);
+
+        assert!(a.send(1, Message::Terminate).is_err());
+        let error = a.send(1, Message::Terminate).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotConnected);
+    }
+}
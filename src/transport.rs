@@ -0,0 +1,169 @@
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender, TrySendError};
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+use crate::message::Message;
+use crate::value::Value;
+
+/// Identifies a node. Ids are unique across all roles, and a proposer's id is
+/// the `proposer_id` of its ballots.
+pub type NodeId = u64;
+
+const CHANNEL_CAPACITY: usize = 100;
+
+/// Moves messages carrying values of type `V` between nodes. Each node owns
+/// one transport.
+pub trait Transport<V: Value = String>: Send {
+    /// Id of the node this transport belongs to.
+    fn id(&self) -> NodeId;
+
+    fn send(&self, to: NodeId, message: Message<V>) -> io::Result<()>;
+
+    /// Waits for the next message addressed to this node. Returns `Ok(None)`
+    /// once `deadline` passes; without a deadline it blocks until a message
+    /// arrives.
+    fn recv_deadline(&self, deadline: Option<Instant>) -> io::Result<Option<Message<V>>>;
+
+    /// Sends `message` to every node in `to`. Paxos tolerates lost messages,
+    /// so failures are logged rather than returned.
+    fn broadcast(&self, to: &[NodeId], message: &Message<V>) {
+        for node in to {
+            if let Err(e) = self.send(*node, message.clone()) {
+                println!("[Transport] Failed to send {:?} to {}: {}", message, node, e);
+            }
+        }
+    }
+}
+
+impl<V: Value, T: Transport<V> + ?Sized> Transport<V> for Box<T> {
+    fn id(&self) -> NodeId {
+        (**self).id()
+    }
+
+    fn send(&self, to: NodeId, message: Message<V>) -> io::Result<()> {
+        (**self).send(to, message)
+    }
+
+    fn recv_deadline(&self, deadline: Option<Instant>) -> io::Result<Option<Message<V>>> {
+        (**self).recv_deadline(deadline)
+    }
+}
+
+type Peers<V> = Arc<RwLock<HashMap<NodeId, Sender<Message<V>>>>>;
+
+/// The inboxes of a set of in-process nodes, which nodes may join at any
+/// time.
+pub struct Network<V = String> {
+    peers: Peers<V>,
+}
+
+impl<V: Value> Network<V> {
+    pub fn new() -> Self {
+        Network { peers: Arc::new(RwLock::new(HashMap::new())) }
+    }
+
+    /// Gives node `id` an inbox and returns its transport. Every transport of
+    /// the network can reach it from then on.
+    pub fn join(&self, id: NodeId) -> ChannelTransport<V> {
+        let (tx, inbox) = bounded(CHANNEL_CAPACITY);
+        self.peers.write().unwrap().insert(id, tx);
+        ChannelTransport { id, inbox, peers: self.peers.clone() }
+    }
+
+    /// Channel feeding node `id`'s inbox, for clients outside the network.
+    pub fn sender(&self, id: NodeId) -> Option<Sender<Message<V>>> {
+        self.peers.read().unwrap().get(&id).cloned()
+    }
+}
+
+impl<V: Value> Default for Network<V> {
+    fn default() -> Self {
+        Network::new()
+    }
+}
+
+/// In-process transport over crossbeam channels, one inbox per node.
+pub struct ChannelTransport<V = String> {
+    id: NodeId,
+    inbox: Receiver<Message<V>>,
+    peers: Peers<V>,
+}
+
+impl<V: Value> ChannelTransport<V> {
+    /// Creates connected transports for every id in `ids`, in the same order.
+    pub fn network(ids: &[NodeId]) -> Vec<ChannelTransport<V>> {
+        let network = Network::new();
+        ids.iter().map(|id| network.join(*id)).collect()
+    }
+
+    /// Channel feeding node `id`'s inbox, for clients outside the network.
+    pub fn sender(&self, id: NodeId) -> Option<Sender<Message<V>>> {
+        self.peers.read().unwrap().get(&id).cloned()
+    }
+}
+
+impl<V: Value> Transport<V> for ChannelTransport<V> {
+    fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// Drops the message if `to`'s inbox is full. Waiting for room could
+    /// deadlock two nodes that send to each other, and the protocol already
+    /// copes with lost messages.
+    fn send(&self, to: NodeId, message: Message<V>) -> io::Result<()> {
+        let peer = self
+            .sender(to)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("unknown node {}", to)))?;
+        peer.try_send(message).map_err(|error| match error {
+            TrySendError::Full(_) => io::Error::new(io::ErrorKind::WouldBlock, format!("inbox of node {} is full", to)),
+            TrySendError::Disconnected(_) => io::Error::new(io::ErrorKind::BrokenPipe, format!("node {} has stopped", to)),
+        })
+    }
+
+    fn recv_deadline(&self, deadline: Option<Instant>) -> io::Result<Option<Message<V>>> {
+        let disconnected = || io::Error::new(io::ErrorKind::BrokenPipe, "inbox disconnected");
+        match deadline {
+            Some(deadline) => match self.inbox.recv_deadline(deadline) {
+                Ok(message) => Ok(Some(message)),
+                Err(RecvTimeoutError::Timeout) => Ok(None),
+                Err(RecvTimeoutError::Disconnected) => Err(disconnected()),
+            },
+            None => self.inbox.recv().map(Some).map_err(|_| disconnected()),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Messages already waiting in `transport`'s inbox.
+    pub(crate) fn drain<V: Value>(transport: &dyn Transport<V>) -> Vec<Message<V>> {
+        std::iter::from_fn(|| transport.recv_deadline(Some(Instant::now())).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_channel_transport_routes_by_id() {
+        let nodes: Vec<ChannelTransport> = ChannelTransport::network(&[3, 7]);
+
+        nodes[0].send(7, Message::Decided(1)).unwrap();
+        nodes[1].broadcast(&[3, 7], &Message::Decided(2));
+
+        assert!(matches!(drain(&nodes[1])[..], [Message::Decided(1), Message::Decided(2)]));
+        assert!(matches!(drain(&nodes[0])[..], [Message::Decided(2)]));
+        assert!(nodes[0].send(5, Message::Terminate).is_err());
+    }
+
+    #[test]
+    fn test_full_inbox_drops_instead_of_blocking() {
+        let nodes: Vec<ChannelTransport> = ChannelTransport::network(&[0, 1]);
+        for slot in 0..CHANNEL_CAPACITY as u64 {
+            nodes[0].send(1, Message::Decided(slot)).unwrap();
+        }
+
+        let error = nodes[0].send(1, Message::Decided(u64::MAX)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(drain(&nodes[1]).len(), CHANNEL_CAPACITY);
+    }
+}
@@ -0,0 +1,42 @@
+//! Values the cluster agrees on.
+//!
+//! Every protocol type is generic over a `Value`. `String` keeps the original
+//! text demo working; `Vec<u8>` carries arbitrary binary blobs, and structured
+//! commands only need to say how they turn into bytes.
+use std::fmt;
+use std::hash::Hash;
+use crate::codec::CodecError;
+use crate::membership::Configuration;
+
+pub trait Value: Clone + fmt::Debug + Ord + Hash + Send + 'static {
+    /// Appends the binary form of `self` to `bytes`.
+    fn encode(&self, bytes: &mut Vec<u8>);
+
+    /// Inverse of `encode`. `bytes` holds exactly one encoded value.
+    fn decode(bytes: &[u8]) -> Result<Self, CodecError>;
+
+    /// The membership change this value stands for, if any.
+    fn reconfiguration(&self) -> Option<&Configuration> {
+        None
+    }
+}
+
+impl Value for String {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, CodecError> {
+        String::from_utf8(bytes.to_vec()).map_err(|_| CodecError::InvalidUtf8)
+    }
+}
+
+impl Value for Vec<u8> {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(self);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, CodecError> {
+        Ok(bytes.to_vec())
+    }
+}
@@ -0,0 +1,318 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use crate::acceptor::{AcceptorState, SlotState};
+use crate::ballot::Ballot;
+use crate::codec::{crc32, MAX_FRAME_LEN};
+use crate::value::Value;
+
+/// Where an acceptor keeps its promises and accepted values.
+///
+/// `persist` must not return until the state is durable: the acceptor calls it
+/// before any `Promise` or `Accept` leaves the node.
+pub trait AcceptorStorage<V = String>: Send {
+    fn persist(&mut self, slot: u64, state: &SlotState<V>) -> io::Result<()>;
+
+    /// Records a promise covering every slot, made to a would-be leader.
+    fn persist_promise(&mut self, ballot: Ballot) -> io::Result<()>;
+
+    /// Replaces everything recorded with `state`, after slots below
+    /// `state.truncated` were dropped.
+    fn compact(&mut self, state: &AcceptorState<V>) -> io::Result<()>;
+
+    /// Everything recorded so far, used to rebuild an acceptor after a restart.
+    fn load(&mut self) -> io::Result<AcceptorState<V>>;
+}
+
+/// Keeps nothing; state is lost when the acceptor stops.
+#[derive(Debug, Default)]
+pub struct MemoryStorage;
+
+impl<V> AcceptorStorage<V> for MemoryStorage {
+    fn persist(&mut self, _slot: u64, _state: &SlotState<V>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn persist_promise(&mut self, _ballot: Ballot) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn compact(&mut self, _state: &AcceptorState<V>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn load(&mut self) -> io::Result<AcceptorState<V>> {
+        Ok(AcceptorState::default())
+    }
+}
+
+/// Append-only write-ahead log, fsynced after every record.
+///
+/// Each record holds the full state of one slot, so replaying the log and
+/// keeping the last record per slot rebuilds the acceptor. A record of just a
+/// ballot is a promise covering every slot, and a record of just a slot is
+/// the truncation point. Compaction rewrites the file from the current state.
+/// Every record carries a CRC-32. A record cut short or garbled by a crash
+/// can only be the last one, and is dropped from the file when the log is
+/// loaded; a bad record anywhere else fails the load.
+pub struct FileStorage {
+    path: PathBuf,
+    file: File,
+}
+
+impl FileStorage {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(FileStorage { path, file })
+    }
+
+    fn append(&mut self, record: &[u8]) -> io::Result<()> {
+        self.file.write_all(&frame(record))?;
+        self.file.sync_data()
+    }
+}
+
+/// `| length: u64 | crc32: u32 | record |`
+fn frame(record: &[u8]) -> Vec<u8> {
+    let mut frame = (record.len() as u64).to_le_bytes().to_vec();
+    frame.extend_from_slice(&crc32(record).to_le_bytes());
+    frame.extend_from_slice(record);
+    frame
+}
+
+fn slot_record<V: Value>(slot: u64, state: &SlotState<V>) -> Vec<u8> {
+    let mut record = vec![];
+    record.extend_from_slice(&slot.to_le_bytes());
+    record.extend_from_slice(&state.max_id.round.to_le_bytes());
+    record.extend_from_slice(&state.max_id.proposer_id.to_le_bytes());
+    match &state.accepted {
+        Some((ballot, value)) => {
+            record.push(1);
+            record.extend_from_slice(&ballot.round.to_le_bytes());
+            record.extend_from_slice(&ballot.proposer_id.to_le_bytes());
+            let mut bytes = vec![];
+            value.encode(&mut bytes);
+            record.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+            record.extend_from_slice(&bytes);
+        }
+        None => record.push(0),
+    }
+    record
+}
+
+fn promise_record(ballot: Ballot) -> Vec<u8> {
+    let mut record = vec![];
+    record.extend_from_slice(&ballot.round.to_le_bytes());
+    record.extend_from_slice(&ballot.proposer_id.to_le_bytes());
+    record
+}
+
+impl<V: Value> AcceptorStorage<V> for FileStorage {
+    fn persist(&mut self, slot: u64, state: &SlotState<V>) -> io::Result<()> {
+        self.append(&slot_record(slot, state))
+    }
+
+    fn persist_promise(&mut self, ballot: Ballot) -> io::Result<()> {
+        self.append(&promise_record(ballot))
+    }
+
+    /// Writes the state to a new file and renames it over the log, so a
+    /// crash leaves either the old log or the new one. The directory is
+    /// synced too, or the rename itself could be lost.
+    fn compact(&mut self, state: &AcceptorState<V>) -> io::Result<()> {
+        let mut bytes = frame(&state.truncated.to_le_bytes());
+        bytes.extend(frame(&promise_record(state.promised)));
+        for (slot, slot_state) in &state.slots {
+            bytes.extend(frame(&slot_record(*slot, slot_state)));
+        }
+        let path = self.path.with_extension("compact");
+        let mut file = File::create(&path)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        std::fs::rename(&path, &self.path)?;
+        let dir = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        File::open(dir)?.sync_all()?;
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        Ok(())
+    }
+
+    fn load(&mut self) -> io::Result<AcceptorState<V>> {
+        let mut bytes = vec![];
+        File::open(&self.path)?.read_to_end(&mut bytes)?;
+        let mut state = AcceptorState::default();
+        let mut reader = Reader { bytes: &bytes };
+        let corrupt = || io::Error::new(io::ErrorKind::InvalidData, "corrupt acceptor log record");
+        let mut valid = 0;
+        while let (Some(len), Some(checksum)) = (reader.u64(), reader.u32()) {
+            // No record is longer than the frame that brought its value.
+            if len > MAX_FRAME_LEN as u64 {
+                return Err(corrupt());
+            }
+            let Some(record) = reader.take(len as usize) else { break };
+            if crc32(record) != checksum {
+                if reader.bytes.is_empty() {
+                    break;
+                }
+                return Err(corrupt());
+            }
+            match decode_record(record) {
+                Some(Record::Slot(slot, slot_state)) => {
+                    state.slots.insert(slot, slot_state);
+                }
+                Some(Record::Promise(ballot)) => state.promised = ballot,
+                Some(Record::Truncate(index)) => state.truncated = index,
+                None => return Err(corrupt()),
+            }
+            valid = bytes.len() - reader.bytes.len();
+        }
+        if valid < bytes.len() {
+            self.file.set_len(valid as u64)?;
+        }
+        Ok(state)
+    }
+}
+
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.bytes.len() < len {
+            return None;
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Some(head)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take(8).map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn ballot(&mut self) -> Option<Ballot> {
+        Some(Ballot::new(self.u64()?, self.u64()?))
+    }
+}
+
+/// Length of a truncation record.
+const TRUNCATE_RECORD_LEN: usize = 8;
+
+/// Length of a promise record. Slot records are always longer.
+const PROMISE_RECORD_LEN: usize = 16;
+
+enum Record<V> {
+    Slot(u64, SlotState<V>),
+    Promise(Ballot),
+    Truncate(u64),
+}
+
+fn decode_record<V: Value>(bytes: &[u8]) -> Option<Record<V>> {
+    let mut reader = Reader { bytes };
+    if bytes.len() == TRUNCATE_RECORD_LEN {
+        return Some(Record::Truncate(reader.u64()?));
+    }
+    if bytes.len() == PROMISE_RECORD_LEN {
+        return Some(Record::Promise(reader.ballot()?));
+    }
+    let slot = reader.u64()?;
+    let max_id = reader.ballot()?;
+    let accepted = match reader.take(1)?[0] {
+        0 => None,
+        1 => {
+            let ballot = reader.ballot()?;
+            let len = reader.u64()? as usize;
+            let value = V::decode(reader.take(len)?).ok()?;
+            Some((ballot, value))
+        }
+        _ => return None,
+    };
+    Some(Record::Slot(slot, SlotState { max_id, accepted }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_keeps_latest_state_per_slot() {
+        let path = std::env::temp_dir().join(format!("paxos-wal-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let promised = SlotState { max_id: Ballot::new(1, 0), accepted: None };
+        let accepted = SlotState { max_id: Ballot::new(2, 1), accepted: Some((Ballot::new(2, 1), "a".to_string())) };
+
+        let mut storage = FileStorage::open(&path).unwrap();
+        storage.persist(0, &promised).unwrap();
+        storage.persist(0, &accepted).unwrap();
+        storage.persist(3, &promised).unwrap();
+        <FileStorage as AcceptorStorage>::persist_promise(&mut storage, Ballot::new(4, 1)).unwrap();
+        drop(storage);
+        // Simulate a crash halfway through writing the next record.
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(&[40, 0, 0]).unwrap();
+
+        let mut storage = FileStorage::open(&path).unwrap();
+        let state: AcceptorState = storage.load().unwrap();
+        storage.persist(4, &promised).unwrap();
+        let reloaded: AcceptorState = storage.load().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(reloaded.slots.len(), 3);
+        assert_eq!(state.slots.len(), 2);
+        assert_eq!(state.slots[&0], accepted);
+        assert_eq!(state.slots[&3], promised);
+        assert_eq!(state.promised, Ballot::new(4, 1));
+    }
+
+    #[test]
+    fn test_garbled_last_record_is_dropped() {
+        let path = std::env::temp_dir().join(format!("paxos-wal-garbled-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let promised: SlotState = SlotState { max_id: Ballot::new(1, 0), accepted: None };
+
+        let mut storage = FileStorage::open(&path).unwrap();
+        storage.persist(0, &promised).unwrap();
+        storage.persist(1, &promised).unwrap();
+        drop(storage);
+        // Simulate a crash that left the last record's bytes half written.
+        let mut bytes = std::fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        std::fs::write(&path, &bytes).unwrap();
+
+        let mut storage = FileStorage::open(&path).unwrap();
+        let state: AcceptorState = storage.load().unwrap();
+        let len = std::fs::metadata(&path).unwrap().len();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(state.slots.keys().collect::<Vec<_>>(), [&0]);
+        assert_eq!(len as usize, bytes.len() / 2);
+    }
+
+    #[test]
+    fn test_garbled_record_before_the_end_fails_the_load() {
+        let path = std::env::temp_dir().join(format!("paxos-wal-middle-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let promised: SlotState = SlotState { max_id: Ballot::new(1, 0), accepted: None };
+
+        let mut storage = FileStorage::open(&path).unwrap();
+        for slot in 0..3 {
+            storage.persist(slot, &promised).unwrap();
+        }
+        drop(storage);
+        let mut bytes = std::fs::read(&path).unwrap();
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0xff;
+        std::fs::write(&path, &bytes).unwrap();
+
+        let mut storage = FileStorage::open(&path).unwrap();
+        let error = AcceptorStorage::<String>::load(&mut storage).unwrap_err();
+        let len = std::fs::metadata(&path).unwrap().len();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(len as usize, bytes.len());
+    }
+}